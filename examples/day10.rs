@@ -1,53 +1,206 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, BufRead, Write};
+
+const REGISTER_NAMES: [&str; 4] = ["x", "y", "z", "w"];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Register(usize);
+
+impl Register {
+    const X: Register = Register(0);
+
+    fn parse(name: &str) -> Option<Register> {
+        REGISTER_NAMES.iter().position(|n| *n == name).map(Register)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Operand {
+    Imm(i32),
+    Reg(Register),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Op {
+    Add,
+    Sub,
+    Mul,
+    Mov,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Condition {
+    Always,
+    Zero(Register),
+    NotZero(Register),
+    Negative(Register),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 enum Instruction {
-    Addx(i32),
-    Noop
+    Noop,
+    // The `x` suffixed forms (`addx 3`) are kept separate from `add x 3` so both can be priced differently.
+    ArithX(Op, Operand),
+    Arith(Op, Register, Operand),
+    Jump(Condition, usize),
 }
 
 impl Instruction {
-    fn cycles(&self) -> usize {
+    fn mnemonic(&self) -> &'static str {
+        match self {
+            Instruction::Noop => "noop",
+            Instruction::ArithX(Op::Add, _) => "addx",
+            Instruction::ArithX(Op::Sub, _) => "subx",
+            Instruction::ArithX(Op::Mul, _) => "mulx",
+            Instruction::ArithX(Op::Mov, _) => "movx",
+            Instruction::Arith(Op::Add, _, _) => "add",
+            Instruction::Arith(Op::Sub, _, _) => "sub",
+            Instruction::Arith(Op::Mul, _, _) => "mul",
+            Instruction::Arith(Op::Mov, _, _) => "mov",
+            Instruction::Jump(Condition::Always, _) => "jmp",
+            Instruction::Jump(Condition::Zero(_), _) => "jz",
+            Instruction::Jump(Condition::NotZero(_), _) => "jnz",
+            Instruction::Jump(Condition::Negative(_), _) => "jlz",
+        }
+    }
+}
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Operand::Imm(value) => write!(f, "{}", value),
+            Operand::Reg(register) => write!(f, "{}", REGISTER_NAMES[register.0]),
+        }
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Instruction::Addx(_) => 2,
-            Instruction::Noop => 1
+            Instruction::Noop => write!(f, "{}", self.mnemonic()),
+            Instruction::ArithX(_, operand) => write!(f, "{} {}", self.mnemonic(), operand),
+            Instruction::Arith(_, register, operand) => write!(f, "{} {} {}", self.mnemonic(), REGISTER_NAMES[register.0], operand),
+            Instruction::Jump(Condition::Always, target) => write!(f, "{} {}", self.mnemonic(), target),
+            Instruction::Jump(Condition::Zero(register) | Condition::NotZero(register) | Condition::Negative(register), target) => {
+                write!(f, "{} {} {}", self.mnemonic(), REGISTER_NAMES[register.0], target)
+            }
         }
     }
 }
 
+// How many cycles each instruction takes, by mnemonic. Anything missing from the table takes one cycle.
+#[derive(Clone, Debug)]
+struct CycleCosts(HashMap<String, usize>);
+
+impl Default for CycleCosts {
+    fn default() -> Self {
+        Self(HashMap::from([
+            ("noop", 1),
+            ("addx", 2),
+            ("subx", 2),
+            ("mulx", 2),
+            ("add", 2),
+            ("sub", 2),
+            ("mul", 2),
+        ].map(|(mnemonic, cycles)| (mnemonic.to_string(), cycles))))
+    }
+}
+
+impl CycleCosts {
+    fn with(mut self, mnemonic: &str, cycles: usize) -> Self {
+        self.0.insert(mnemonic.to_string(), cycles);
+        self
+    }
+
+    fn cycles(&self, instruction: &Instruction) -> usize {
+        self.0.get(instruction.mnemonic()).copied().unwrap_or(1).max(1)
+    }
+}
+
 #[derive(Default)]
 struct Cpu {
     instructions: Vec<Instruction>,
+    costs: CycleCosts,
     pc: usize,
-    x: i32,
+    registers: [i32; REGISTER_NAMES.len()],
     cycle: usize,
     cycles_left_in_instruction: usize,
 }
 
 impl Cpu {
     fn new(instructions: Vec<Instruction>) -> Self {
+        Self::with_costs(instructions, CycleCosts::default())
+    }
+
+    fn with_costs(instructions: Vec<Instruction>, costs: CycleCosts) -> Self {
+        let mut registers = [0; REGISTER_NAMES.len()];
+        registers[Register::X.0] = 1;
         Self {
             instructions,
+            costs,
             cycle: 1,
-            x: 1,
+            registers,
             ..Default::default()
         }
     }
 
+    fn x(&self) -> i32 {
+        self.reg(Register::X)
+    }
+
+    fn reg(&self, register: Register) -> i32 {
+        self.registers[register.0]
+    }
+
+    fn operand(&self, operand: Operand) -> i32 {
+        match operand {
+            Operand::Imm(value) => value,
+            Operand::Reg(register) => self.reg(register)
+        }
+    }
+
+    fn apply(&mut self, op: Op, register: Register, operand: Operand) {
+        let value = self.operand(operand);
+        let target = &mut self.registers[register.0];
+        // Registers wrap around like real hardware instead of bringing down the whole VM.
+        match op {
+            Op::Add => *target = target.wrapping_add(value),
+            Op::Sub => *target = target.wrapping_sub(value),
+            Op::Mul => *target = target.wrapping_mul(value),
+            Op::Mov => *target = value,
+        }
+    }
+
     fn step(&mut self) -> bool {
-        let instruction = &self.instructions[self.pc];
+        let Some(instruction) = self.instructions.get(self.pc).copied() else {
+            return false;
+        };
         if self.cycles_left_in_instruction == 0 {
-            self.cycles_left_in_instruction = instruction.cycles();
+            self.cycles_left_in_instruction = self.costs.cycles(&instruction);
         }
 
         self.cycles_left_in_instruction -= 1;
         if self.cycles_left_in_instruction == 0 {
+            self.pc += 1;
             match instruction {
-                Instruction::Addx(addx) => {
-                    self.x += *addx;
-                },
-                _ => ()
+                Instruction::Noop => (),
+                Instruction::ArithX(op, operand) => self.apply(op, Register::X, operand),
+                Instruction::Arith(op, register, operand) => self.apply(op, register, operand),
+                Instruction::Jump(condition, target) => {
+                    let taken = match condition {
+                        Condition::Always => true,
+                        Condition::Zero(register) => self.reg(register) == 0,
+                        Condition::NotZero(register) => self.reg(register) != 0,
+                        Condition::Negative(register) => self.reg(register) < 0,
+                    };
+                    if taken {
+                        self.pc = target;
+                    }
+                }
             }
 
-            self.pc += 1;
-            if self.pc == self.instructions.len() {
+            if self.pc >= self.instructions.len() {
                 return false;
             }
         }
@@ -57,59 +210,572 @@ impl Cpu {
     }
 }
 
-fn parse(input: &str) -> Vec<Instruction> {
-    input.lines()
-        .map(|line| {
-            if line == "noop" {
-                Instruction::Noop
-            } else {
-                let amount = line.strip_prefix("addx ").unwrap();
-                Instruction::Addx(i32::from_str_radix(amount, 10).unwrap())
+#[derive(Debug, PartialEq, Eq)]
+struct AsmError {
+    line: usize,
+    message: String,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for AsmError {}
+
+// Assembles a program. Besides the puzzle's `noop` and `addx`, this understands `subx`/`mulx`, the
+// general `add`/`sub`/`mul`/`mov <reg> <operand>`, and `jmp`/`jz`/`jnz`/`jlz` to `label:`s.
+fn assemble(input: &str) -> Result<Vec<Instruction>, AsmError> {
+    let lines = input.lines()
+        .enumerate()
+        .map(|(idx, line)| (idx + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with(';'))
+        .collect::<Vec<_>>();
+
+    let mut labels = HashMap::new();
+    let mut num_instructions = 0;
+    for (line, text) in &lines {
+        match text.strip_suffix(':') {
+            Some(label) => {
+                if labels.insert(label, num_instructions).is_some() {
+                    return Err(AsmError { line: *line, message: format!("duplicate label `{}`", label) });
+                }
+            },
+            None => num_instructions += 1
+        }
+    }
+
+    let mut instructions = vec![];
+    for (line, text) in lines {
+        if text.ends_with(':') {
+            continue;
+        }
+        let error = |message: String| AsmError { line, message };
+        let parts = text.split_whitespace().collect::<Vec<_>>();
+        let arg = |idx: usize| parts.get(idx).copied().ok_or_else(|| error(format!("`{}` is missing an argument", parts[0])));
+        let register = |idx: usize| arg(idx).and_then(|name| Register::parse(name).ok_or_else(|| error(format!("unknown register `{}`", name))));
+        let operand = |idx: usize| arg(idx).and_then(|value| {
+            Register::parse(value)
+                .map(Operand::Reg)
+                .or_else(|| value.parse().ok().map(Operand::Imm))
+                .ok_or_else(|| error(format!("invalid operand `{}`", value)))
+        });
+        let label = |idx: usize| arg(idx).and_then(|name| labels.get(name).copied().ok_or_else(|| error(format!("unknown label `{}`", name))));
+        let op = |mnemonic: &str| match mnemonic {
+            "add" => Some(Op::Add),
+            "sub" => Some(Op::Sub),
+            "mul" => Some(Op::Mul),
+            "mov" => Some(Op::Mov),
+            _ => None
+        };
+
+        let (expected_args, instruction) = match parts[0] {
+            "noop" => (1, Instruction::Noop),
+            "jmp" => (2, Instruction::Jump(Condition::Always, label(1)?)),
+            "jz" => (3, Instruction::Jump(Condition::Zero(register(1)?), label(2)?)),
+            "jnz" => (3, Instruction::Jump(Condition::NotZero(register(1)?), label(2)?)),
+            "jlz" => (3, Instruction::Jump(Condition::Negative(register(1)?), label(2)?)),
+            mnemonic => match (op(mnemonic), mnemonic.strip_suffix('x').and_then(op)) {
+                (Some(op), _) => (3, Instruction::Arith(op, register(1)?, operand(2)?)),
+                (None, Some(op)) => (2, Instruction::ArithX(op, operand(1)?)),
+                (None, None) => return Err(error(format!("unknown instruction `{}`", mnemonic)))
             }
-        })
-        .collect()
+        };
+        if parts.len() > expected_args {
+            return Err(error(format!("too many arguments to `{}`", parts[0])));
+        }
+        instructions.push(instruction);
+    }
+
+    Ok(instructions)
+}
+
+fn parse(input: &str) -> Vec<Instruction> {
+    assemble(input).unwrap()
 }
 
 fn calc_sum(input: &str) -> i32 {
     let mut cpu = Cpu::new(parse(input));
     let mut sum = 0;
-    let interesting_cycles = vec![20, 60, 100, 140, 180, 220];
+    let interesting_cycles = [20, 60, 100, 140, 180, 220];
     while cpu.step() {
         if interesting_cycles.contains(&cpu.cycle) {
-            //println!("cpu: {} {}", cpu.cycle, cpu.x);
-            sum += cpu.cycle as i32 * cpu.x;
+            //println!("cpu: {} {}", cpu.cycle, cpu.x());
+            sum += cpu.cycle as i32 * cpu.x();
         }
     }
     sum
 }
 
-fn print(input: &str) {
-    let mut cpu = Cpu::new(parse(input));
-    let mut crt_x = 1;
-    let mut pixels = "".to_string();
-    loop {
-        if crt_x >= cpu.x && crt_x <= cpu.x + 2 {
-            pixels += "#";
-        } else {
-            pixels += ".";
+const CRT_WIDTH: usize = 40;
+
+#[derive(Clone, Debug)]
+struct Crt {
+    width: usize,
+    height: usize,
+    sprite_width: usize,
+    // Where the sprite starts relative to `x`. The puzzle's 3 pixel sprite is centered on `x`, so -1.
+    sprite_offset: i32,
+    pixels: Vec<Vec<bool>>,
+    // The pixel the beam draws next, once it passes the last row it starts over at the top.
+    beam: usize,
+}
+
+impl Default for Crt {
+    fn default() -> Self {
+        Self::new(CRT_WIDTH, 6, 3, -1)
+    }
+}
+
+impl Crt {
+    fn new(width: usize, height: usize, sprite_width: usize, sprite_offset: i32) -> Self {
+        assert!(width > 0 && height > 0);
+        Self {
+            width,
+            height,
+            sprite_width,
+            sprite_offset,
+            pixels: vec![vec![false; width]; height],
+            beam: 0
         }
-        crt_x += 1;
-        if crt_x > 40 {
-            println!("{}", pixels);
-            pixels = "".to_string();
-            crt_x = 1;
+    }
+
+    // The pixel drawn during `cycle`.
+    fn beam_at(&self, cycle: usize) -> (usize, usize) {
+        let pixel = (cycle - 1) % (self.width * self.height);
+        (pixel % self.width, pixel / self.width)
+    }
+
+    fn is_lit(&self, col: usize, x: i32) -> bool {
+        let start = x as i64 + self.sprite_offset as i64;
+        (start..start + self.sprite_width as i64).contains(&(col as i64))
+    }
+
+    fn draw(&mut self, x: i32) -> bool {
+        let (col, row) = (self.beam % self.width, self.beam / self.width);
+        let lit = self.is_lit(col, x);
+        self.pixels[row][col] = lit;
+        self.beam = (self.beam + 1) % (self.width * self.height);
+        lit
+    }
+
+    // Draws one pixel per cycle until the program is done.
+    fn run(&mut self, cpu: &mut Cpu) {
+        loop {
+            self.draw(cpu.x());
+            if !cpu.step() {
+                break;
+            }
         }
+    }
+}
+
+impl fmt::Display for Crt {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", framebuffer_to_string(&self.pixels))
+    }
+}
+
+fn render(input: &str) -> Vec<[bool; CRT_WIDTH]> {
+    let mut crt = Crt::default();
+    crt.run(&mut Cpu::new(parse(input)));
+    crt.pixels.iter()
+        .map(|row| row.as_slice().try_into().unwrap())
+        .collect()
+}
+
+fn framebuffer_to_string<R: AsRef<[bool]>>(framebuffer: &[R]) -> String {
+    framebuffer.iter()
+        .map(|row| row.as_ref().iter().map(|lit| if *lit { '#' } else { '.' }).collect::<String>() + "\n")
+        .collect()
+}
+
+const GLYPH_WIDTH: usize = 4;
+const GLYPH_HEIGHT: usize = 6;
 
-        if !cpu.step() {
-            break;
+// The letters that show up in puzzle answers, each 4x6 pixels with a blank column in between.
+const FONT: [(char, &str); 18] = [
+    ('A', ".##.#..##..######..##..#"),
+    ('B', "###.#..####.#..##..####."),
+    ('C', ".##.#..##...#...#..#.##."),
+    ('E', "#####...###.#...#...####"),
+    ('F', "#####...###.#...#...#..."),
+    ('G', ".##.#..##...#.###..#.###"),
+    ('H', "#..##..######..##..##..#"),
+    ('I', ".###..#...#...#...#..###"),
+    ('J', "..##...#...#...##..#.##."),
+    ('K', "#..##.#.##..#.#.#.#.#..#"),
+    ('L', "#...#...#...#...#...####"),
+    ('O', ".##.#..##..##..##..#.##."),
+    ('P', "###.#..##..####.#...#..."),
+    ('R', "###.#..##..####.#.#.#..#"),
+    ('S', ".####...#....##....####."),
+    ('U', "#..##..##..##..##..#.##."),
+    ('Y', "#...#....#.#..#...#...#."),
+    ('Z', "####...#..#..#..#...####"),
+];
+
+#[derive(Debug, PartialEq, Eq)]
+enum OcrError {
+    WrongHeight(usize),
+    UnknownGlyph { index: usize, pixels: String },
+}
+
+impl fmt::Display for OcrError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OcrError::WrongHeight(height) => write!(f, "expected {} rows of pixels, got {}", GLYPH_HEIGHT, height),
+            OcrError::UnknownGlyph { index, pixels } => write!(f, "unknown glyph #{}: {}", index, pixels),
         }
     }
-    println!("{}", pixels);
+}
+
+impl std::error::Error for OcrError {}
+
+fn ocr<R: AsRef<[bool]>>(framebuffer: &[R]) -> Result<String, OcrError> {
+    if framebuffer.len() != GLYPH_HEIGHT {
+        return Err(OcrError::WrongHeight(framebuffer.len()));
+    }
+
+    let width = framebuffer.first().map_or(0, |row| row.as_ref().len());
+    (0..(width + 1) / (GLYPH_WIDTH + 1))
+        .map(|index| {
+            let left = index * (GLYPH_WIDTH + 1);
+            let pixels = framebuffer.iter()
+                .flat_map(|row| &row.as_ref()[left..left + GLYPH_WIDTH])
+                .map(|lit| if *lit { '#' } else { '.' })
+                .collect::<String>();
+            FONT.iter()
+                .find(|(_, glyph)| *glyph == pixels)
+                .map(|(c, _)| *c)
+                .ok_or(OcrError::UnknownGlyph { index, pixels })
+        })
+        .collect()
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct CycleTrace {
+    cycle: usize,
+    pc: usize,
+    instruction: String,
+    // Which of the instruction's `cycles` this is, starting at 1.
+    phase: usize,
+    cycles: usize,
+    x_during: i32,
+    x_after: i32,
+    pixel: (usize, usize),
+    lit: bool,
+}
+
+impl Cpu {
+    // Runs the program to completion, recording what happens in every cycle on a CRT shaped like `crt`.
+    fn trace(mut self, crt: &Crt) -> Vec<CycleTrace> {
+        let mut rows = vec![];
+        loop {
+            let Some(instruction) = self.instructions.get(self.pc) else {
+                return rows;
+            };
+            let cycles = self.costs.cycles(instruction);
+            let phase = match self.cycles_left_in_instruction {
+                0 => 1,
+                left => cycles - left + 1
+            };
+            let pixel = crt.beam_at(self.cycle);
+            let mut row = CycleTrace {
+                cycle: self.cycle,
+                pc: self.pc,
+                instruction: instruction.to_string(),
+                phase,
+                cycles,
+                x_during: self.x(),
+                x_after: self.x(),
+                pixel,
+                lit: crt.is_lit(pixel.0, self.x()),
+            };
+
+            let running = self.step();
+            row.x_after = self.x();
+            rows.push(row);
+            if !running {
+                return rows;
+            }
+        }
+    }
+}
+
+fn trace_to_csv(rows: &[CycleTrace]) -> String {
+    let mut csv = "cycle,pc,instruction,phase,cycles,x_during,x_after,pixel_x,pixel_y,lit\n".to_string();
+    for row in rows {
+        csv += &format!("{},{},{},{},{},{},{},{},{},{}\n", row.cycle, row.pc, row.instruction, row.phase, row.cycles,
+                        row.x_during, row.x_after, row.pixel.0, row.pixel.1, row.lit);
+    }
+    csv
+}
+
+fn trace_to_json_lines(rows: &[CycleTrace]) -> String {
+    rows.iter()
+        .map(|row| {
+            format!("{{\"cycle\":{},\"pc\":{},\"instruction\":\"{}\",\"phase\":{},\"cycles\":{},\"x_during\":{},\"x_after\":{},\"pixel_x\":{},\"pixel_y\":{},\"lit\":{}}}\n",
+                    row.cycle, row.pc, row.instruction, row.phase, row.cycles, row.x_during, row.x_after, row.pixel.0, row.pixel.1, row.lit)
+        })
+        .collect()
+}
+
+// Everything about a `Cpu` that changes while it runs.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct CpuState {
+    pc: usize,
+    registers: [i32; REGISTER_NAMES.len()],
+    cycle: usize,
+    cycles_left_in_instruction: usize,
+}
+
+impl Cpu {
+    fn snapshot(&self) -> CpuState {
+        CpuState {
+            pc: self.pc,
+            registers: self.registers,
+            cycle: self.cycle,
+            cycles_left_in_instruction: self.cycles_left_in_instruction
+        }
+    }
+
+    fn restore(&mut self, state: CpuState) {
+        self.pc = state.pc;
+        self.registers = state.registers;
+        self.cycle = state.cycle;
+        self.cycles_left_in_instruction = state.cycles_left_in_instruction;
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Breakpoint {
+    // Stops right before the instruction at this address starts executing.
+    Pc(usize),
+    // Stops when this cycle begins.
+    Cycle(usize),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum StopReason {
+    Stepped,
+    Breakpoint(Breakpoint),
+    Watchpoint { register: Register, old: i32, new: i32 },
+    Halted,
+}
+
+impl fmt::Display for StopReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StopReason::Stepped => write!(f, "stepped"),
+            StopReason::Breakpoint(Breakpoint::Pc(pc)) => write!(f, "breakpoint at pc {}", pc),
+            StopReason::Breakpoint(Breakpoint::Cycle(cycle)) => write!(f, "breakpoint at cycle {}", cycle),
+            StopReason::Watchpoint { register, old, new } => write!(f, "{} changed from {} to {}", REGISTER_NAMES[register.0], old, new),
+            StopReason::Halted => write!(f, "halted"),
+        }
+    }
+}
+
+// Steps a `Cpu` one cycle at a time, keeping every previous state around so it can also step backwards.
+// Between steps, `cpu.cycle` is the cycle that's about to run and the registers hold their values
+// *during* that cycle, which are also their values *after* the previous one.
+struct Debugger {
+    cpu: Cpu,
+    breakpoints: Vec<Breakpoint>,
+    watchpoints: Vec<Register>,
+    history: Vec<CpuState>,
+    halted: bool,
+    // Whether anything has run yet. Until then, `continue` also stops at a breakpoint on the very first state.
+    started: bool,
+    tracing: bool,
+}
+
+impl Debugger {
+    fn new(cpu: Cpu) -> Self {
+        Self {
+            cpu,
+            breakpoints: vec![],
+            watchpoints: vec![],
+            history: vec![],
+            halted: false,
+            started: false,
+            tracing: false
+        }
+    }
+
+    fn breakpoint_hit(&self) -> Option<Breakpoint> {
+        self.breakpoints.iter().copied().find(|breakpoint| match breakpoint {
+            Breakpoint::Pc(pc) => self.cpu.cycles_left_in_instruction == 0 && self.cpu.pc == *pc,
+            Breakpoint::Cycle(cycle) => self.cpu.cycle == *cycle,
+        })
+    }
+
+    fn step(&mut self) -> StopReason {
+        if self.halted {
+            return StopReason::Halted;
+        }
+
+        let before = self.cpu.snapshot();
+        self.history.push(before);
+        self.started = true;
+        self.halted = !self.cpu.step();
+
+        // The last cycle can still change registers, so watchpoints come before halting.
+        for register in &self.watchpoints {
+            let (old, new) = (before.registers[register.0], self.cpu.reg(*register));
+            if old != new {
+                return StopReason::Watchpoint { register: *register, old, new };
+            }
+        }
+        if self.halted {
+            return StopReason::Halted;
+        }
+        match self.breakpoint_hit() {
+            Some(breakpoint) => StopReason::Breakpoint(breakpoint),
+            None => StopReason::Stepped
+        }
+    }
+
+    fn step_back(&mut self) -> bool {
+        match self.history.pop() {
+            Some(state) => {
+                self.cpu.restore(state);
+                self.halted = false;
+                true
+            },
+            None => false
+        }
+    }
+
+    fn cont(&mut self, out: &mut impl Write) -> io::Result<StopReason> {
+        if !self.started {
+            self.started = true;
+            if let Some(breakpoint) = self.breakpoint_hit() {
+                return Ok(StopReason::Breakpoint(breakpoint));
+            }
+        }
+        loop {
+            let reason = self.step();
+            if self.tracing {
+                writeln!(out, "{}", self.describe())?;
+            }
+            if reason != StopReason::Stepped {
+                return Ok(reason);
+            }
+        }
+    }
+
+    fn describe(&self) -> String {
+        let registers = REGISTER_NAMES.iter()
+            .zip(self.cpu.registers)
+            .map(|(name, value)| format!("{}={}", name, value))
+            .collect::<Vec<_>>()
+            .join(" ");
+        let instruction = self.cpu.instructions.get(self.cpu.pc).map_or("-", |instruction| instruction.mnemonic());
+        format!("cycle {} pc {} ({}) {}", self.cpu.cycle, self.cpu.pc, instruction, registers)
+    }
+
+    // Runs a single command, returning false once the session should end.
+    fn command(&mut self, line: &str, out: &mut impl Write) -> io::Result<bool> {
+        let parts = line.split_whitespace().collect::<Vec<_>>();
+        let count = |idx: usize| parts.get(idx).and_then(|n| n.parse().ok()).unwrap_or(1);
+        match parts.as_slice() {
+            [] => (),
+            ["step" | "s", ..] => {
+                let mut reason = StopReason::Stepped;
+                for _ in 0..count(1) {
+                    reason = self.step();
+                    if reason != StopReason::Stepped {
+                        break;
+                    }
+                }
+                writeln!(out, "{}: {}", reason, self.describe())?;
+            },
+            ["back" | "b", ..] => {
+                for _ in 0..count(1) {
+                    if !self.step_back() {
+                        writeln!(out, "already at the start")?;
+                        break;
+                    }
+                }
+                writeln!(out, "{}", self.describe())?;
+            },
+            ["continue" | "c"] => {
+                let reason = self.cont(out)?;
+                writeln!(out, "{}: {}", reason, self.describe())?;
+            },
+            ["break", kind, value] => match (*kind, value.parse()) {
+                ("pc", Ok(pc)) => self.breakpoints.push(Breakpoint::Pc(pc)),
+                // Cycles only go forward, so a cycle that's already been reached can't be hit again.
+                ("cycle", Ok(cycle)) if cycle < self.cpu.cycle || (self.started && cycle == self.cpu.cycle) => {
+                    writeln!(out, "cycle {} has already passed", cycle)?
+                },
+                ("cycle", Ok(cycle)) => self.breakpoints.push(Breakpoint::Cycle(cycle)),
+                _ => writeln!(out, "usage: break pc|cycle <n>")?
+            },
+            ["watch", name] => match Register::parse(name) {
+                Some(register) => self.watchpoints.push(register),
+                None => writeln!(out, "unknown register `{}`", name)?
+            },
+            ["delete"] => {
+                self.breakpoints.clear();
+                self.watchpoints.clear();
+            },
+            ["print" | "p", name] => match (*name, Register::parse(name)) {
+                ("pc", _) => writeln!(out, "pc = {}", self.cpu.pc)?,
+                ("cycle", _) => writeln!(out, "cycle = {}", self.cpu.cycle)?,
+                (_, Some(register)) => writeln!(out, "{} = {}", name, self.cpu.reg(register))?,
+                _ => writeln!(out, "unknown register `{}`", name)?
+            },
+            ["trace"] => {
+                self.tracing = !self.tracing;
+                writeln!(out, "tracing {}", if self.tracing { "on" } else { "off" })?;
+            },
+            ["quit" | "q"] => return Ok(false),
+            _ => writeln!(out, "unknown command `{}`", line.trim())?
+        }
+        Ok(true)
+    }
+
+    fn repl(&mut self, input: impl BufRead, mut out: impl Write) -> io::Result<()> {
+        writeln!(out, "{}", self.describe())?;
+        for line in input.lines() {
+            if !self.command(&line?, &mut out)? {
+                break;
+            }
+        }
+        Ok(())
+    }
 }
 
 fn main() {
+    if std::env::args().nth(1).as_deref() == Some("debug") {
+        let mut debugger = Debugger::new(Cpu::new(parse(include_str!("day10.txt"))));
+        debugger.repl(io::stdin().lock(), io::stdout()).unwrap();
+        return;
+    }
+
+    match (std::env::args().nth(1).as_deref(), std::env::args().nth(2).as_deref()) {
+        (Some("trace"), Some("json")) => return print!("{}", trace_to_json_lines(&Cpu::new(parse(include_str!("day10.txt"))).trace(&Crt::default()))),
+        (Some("trace"), _) => return print!("{}", trace_to_csv(&Cpu::new(parse(include_str!("day10.txt"))).trace(&Crt::default()))),
+        // `costs addx=1 noop=3 ...` draws the screen with different instruction timings.
+        (Some("costs"), _) => {
+            let costs = std::env::args().skip(2).fold(CycleCosts::default(), |costs, arg| {
+                let (mnemonic, cycles) = arg.split_once('=').expect("costs look like `addx=1`");
+                costs.with(mnemonic, cycles.parse().unwrap())
+            });
+            let mut crt = Crt::default();
+            crt.run(&mut Cpu::with_costs(parse(include_str!("day10.txt")), costs));
+            return print!("{}", crt);
+        }
+        _ => ()
+    }
+
     println!("sum: {}", calc_sum(include_str!("day10.txt")));
-    print(include_str!("day10.txt"));
+    let framebuffer = render(include_str!("day10.txt"));
+    print!("{}", framebuffer_to_string(&framebuffer));
+    println!("letters: {}", ocr(&framebuffer).unwrap());
 }
 
 #[cfg(test)]
@@ -120,6 +786,191 @@ mod tests {
     fn test() {
         let input = include_str!("day10_example.txt");
         assert_eq!(calc_sum(input), 13140);
-        print(input);
+        assert_eq!(framebuffer_to_string(&render(input)), r"##..##..##..##..##..##..##..##..##..##..
+###...###...###...###...###...###...###.
+####....####....####....####....####....
+#####.....#####.....#####.....#####.....
+######......######......######......####
+#######.......#######.......#######.....
+");
+    }
+
+    #[test]
+    fn test_vm() {
+        let input = r"mov y 5
+loop:
+addx 2
+sub y 1
+jnz y loop
+mulx y
+addx 3
+mul z x
+subx -4";
+        let mut cpu = Cpu::new(assemble(input).unwrap());
+        while cpu.step() {}
+        assert_eq!(cpu.x(), 7);
+        assert_eq!(cpu.reg(Register(1)), 0);
+        assert_eq!(cpu.reg(Register(2)), 0);
+        assert_eq!(cpu.cycle, 1 + 5 * 5 + 2 + 2 + 2 + 2);
+
+        let costs = CycleCosts::default().with("addx", 1).with("jnz", 3);
+        let mut cpu = Cpu::with_costs(assemble(input).unwrap(), costs);
+        while cpu.step() {}
+        assert_eq!(cpu.cycle, 1 + 5 * 6 + 2 + 1 + 2 + 2);
+
+        assert_eq!(assemble("addx"), Err(AsmError { line: 1, message: "`addx` is missing an argument".to_string() }));
+        assert_eq!(assemble("noop\njmp nowhere"), Err(AsmError { line: 2, message: "unknown label `nowhere`".to_string() }));
+        assert_eq!(assemble("add q 1"), Err(AsmError { line: 1, message: "unknown register `q`".to_string() }));
+
+        let mut cpu = Cpu::new(assemble("mov x 100000\nmul x x\nmov y -2147483648\nsub y 1\nadd y y").unwrap());
+        while cpu.step() {}
+        assert_eq!(cpu.x(), 100000i32.wrapping_mul(100000));
+        assert_eq!(cpu.reg(Register(1)), -2);
+        assert_eq!(assemble("divx 2"), Err(AsmError { line: 1, message: "unknown instruction `divx`".to_string() }));
+        assert_eq!(assemble("a:\na:"), Err(AsmError { line: 2, message: "duplicate label `a`".to_string() }));
+    }
+
+    #[test]
+    fn test_ocr() {
+        let screen = r"####.####..##..####.####.#....#..#.####.
+#....#....#..#....#.#....#....#..#.#....
+###..###..#......#..###..#....####.###..
+#....#....#.....#...#....#....#..#.#....
+#....#....#..#.#....#....#....#..#.#....
+#....####..##..####.####.####.#..#.####.";
+        let framebuffer = screen.lines()
+            .map(|line| std::array::from_fn(|x| line.as_bytes()[x] == b'#'))
+            .collect::<Vec<[bool; CRT_WIDTH]>>();
+        assert_eq!(ocr(&framebuffer), Ok("FECZELHE".to_string()));
+        assert_eq!(ocr(&framebuffer[..5]), Err(OcrError::WrongHeight(5)));
+
+        let example = render(include_str!("day10_example.txt"));
+        assert_eq!(ocr(&example), Err(OcrError::UnknownGlyph { index: 0, pixels: "##..###.################".to_string() }));
+    }
+
+    #[test]
+    fn test_debugger() {
+        let mut debugger = Debugger::new(Cpu::new(parse("noop\naddx 3\naddx -5")));
+        assert_eq!(debugger.step(), StopReason::Stepped);
+        assert_eq!(debugger.step(), StopReason::Stepped);
+        // x only changes once the second cycle of `addx 3` is over.
+        assert_eq!(debugger.cpu.x(), 1);
+        debugger.watchpoints.push(Register::X);
+        assert_eq!(debugger.step(), StopReason::Watchpoint { register: Register::X, old: 1, new: 4 });
+        assert_eq!(debugger.cpu.cycle, 4);
+        assert!(debugger.step_back());
+        assert_eq!((debugger.cpu.cycle, debugger.cpu.x()), (3, 1));
+
+        // A change during the very last cycle still trips the watchpoint.
+        let mut debugger = Debugger::new(Cpu::new(parse("noop\naddx 3")));
+        debugger.watchpoints.push(Register::X);
+        assert_eq!(debugger.step(), StopReason::Stepped);
+        assert_eq!(debugger.step(), StopReason::Stepped);
+        assert_eq!(debugger.step(), StopReason::Watchpoint { register: Register::X, old: 1, new: 4 });
+        assert_eq!(debugger.step(), StopReason::Halted);
+
+        // Breakpoints on the starting state are hit, ones that have passed are refused.
+        let mut debugger = Debugger::new(Cpu::new(parse("noop\naddx 3\naddx -5")));
+        let mut out = vec![];
+        debugger.repl("break pc 0\ncontinue\ncontinue\nbreak cycle 1\nbreak cycle 4\nback\ncontinue".as_bytes(), &mut out).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "cycle 1 pc 0 (noop) x=1 y=0 z=0 w=0
+breakpoint at pc 0: cycle 1 pc 0 (noop) x=1 y=0 z=0 w=0
+halted: cycle 5 pc 3 (-) x=-1 y=0 z=0 w=0
+cycle 1 has already passed
+cycle 4 has already passed
+cycle 5 pc 2 (addx) x=4 y=0 z=0 w=0
+halted: cycle 5 pc 3 (-) x=-1 y=0 z=0 w=0
+");
+
+        let script = "break cycle 3
+continue
+print x
+step
+print x
+print cycle
+back 2
+print pc
+trace
+continue
+nonsense
+quit
+step";
+        let mut debugger = Debugger::new(Cpu::new(parse("noop\naddx 3\naddx -5")));
+        let mut out = vec![];
+        debugger.repl(script.as_bytes(), &mut out).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "cycle 1 pc 0 (noop) x=1 y=0 z=0 w=0
+breakpoint at cycle 3: cycle 3 pc 1 (addx) x=1 y=0 z=0 w=0
+x = 1
+stepped: cycle 4 pc 2 (addx) x=4 y=0 z=0 w=0
+x = 4
+cycle = 4
+cycle 2 pc 1 (addx) x=1 y=0 z=0 w=0
+pc = 1
+tracing on
+cycle 3 pc 1 (addx) x=1 y=0 z=0 w=0
+breakpoint at cycle 3: cycle 3 pc 1 (addx) x=1 y=0 z=0 w=0
+unknown command `nonsense`
+");
+    }
+
+    #[test]
+    fn test_trace() {
+        let rows = Cpu::new(parse("noop\naddx 3\naddx -5")).trace(&Crt::default());
+        assert_eq!(trace_to_csv(&rows), "cycle,pc,instruction,phase,cycles,x_during,x_after,pixel_x,pixel_y,lit
+1,0,noop,1,1,1,1,0,0,true
+2,1,addx 3,1,2,1,1,1,0,true
+3,1,addx 3,2,2,1,4,2,0,true
+4,2,addx -5,1,2,4,4,3,0,true
+5,2,addx -5,2,2,4,-1,4,0,true
+");
+        assert_eq!(trace_to_json_lines(&rows[..1]), "{\"cycle\":1,\"pc\":0,\"instruction\":\"noop\",\"phase\":1,\"cycles\":1,\"x_during\":1,\"x_after\":1,\"pixel_x\":0,\"pixel_y\":0,\"lit\":true}\n");
+
+        // The trace has to agree with both parts of the puzzle.
+        let input = include_str!("day10_example.txt");
+        let rows = Cpu::new(parse(input)).trace(&Crt::default());
+        assert_eq!(rows.len(), 240);
+        let sum = rows.iter()
+            .filter(|row| [20, 60, 100, 140, 180, 220].contains(&row.cycle))
+            .map(|row| row.cycle as i32 * row.x_during)
+            .sum::<i32>();
+        assert_eq!(sum, calc_sum(input));
+        let framebuffer = render(input);
+        assert!(rows.iter().all(|row| framebuffer[row.pixel.1][row.pixel.0] == row.lit));
+
+        // Other geometries trace the same pixels their CRT draws.
+        let mut crt = Crt::new(80, 2, 5, -2);
+        let rows = Cpu::new(parse(input)).trace(&crt);
+        crt.run(&mut Cpu::new(parse(input)));
+        assert_eq!(rows[40].pixel, (40, 0));
+        assert_eq!(rows[160].pixel, (0, 0));
+        assert!(rows[80..].iter().all(|row| crt.pixels[row.pixel.1][row.pixel.0] == row.lit));
+        let default_rows = Cpu::new(parse(input)).trace(&Crt::default());
+        assert!(rows.iter().zip(&default_rows).any(|(a, b)| a.lit != b.lit));
+    }
+
+    #[test]
+    fn test_crt_geometry() {
+        let input = include_str!("day10_example.txt");
+        let mut crt = Crt::default();
+        crt.run(&mut Cpu::new(parse(input)));
+        assert_eq!(crt.pixels.len(), 6);
+        assert_eq!(framebuffer_to_string(&crt.pixels), framebuffer_to_string(&render(input)));
+
+        // At 80 columns the same 240 cycles fill three rows, but `x` never gets past the first 40.
+        let mut crt = Crt::new(80, 3, 3, -1);
+        crt.run(&mut Cpu::new(parse(input)));
+        let first_row = "##..##..##..##..##..##..##..##..##..##..".to_string() + &".".repeat(40);
+        assert_eq!(crt.to_string().lines().next(), Some(first_row.as_str()));
+
+        // A single pixel sprite at `x` itself.
+        let mut crt = Crt::new(4, 1, 1, 0);
+        crt.run(&mut Cpu::new(parse("noop\naddx 1\nnoop")));
+        assert_eq!(crt.to_string(), ".#..\n");
+
+        // Running past the last row starts over at the top.
+        let mut crt = Crt::new(2, 1, 1, 0);
+        crt.run(&mut Cpu::new(parse("addx -1\nnoop\nnoop")));
+        assert_eq!(crt.to_string(), "#.\n");
+        assert!(!crt.is_lit(0, i32::MAX));
     }
 }
@@ -1,133 +1,720 @@
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use std::fmt;
 use itertools::Itertools;
+use nom::branch::alt;
+use nom::bytes::complete::tag;
+use nom::character::complete::{char, digit1, multispace0, one_of};
+use nom::combinator::{all_consuming, map_res, value};
+use nom::IResult;
+use nom::multi::fold_many0;
+use nom::sequence::{delimited, pair, preceded};
+use petgraph::dot::Dot;
+use petgraph::graph::DiGraph;
 
 #[derive(Clone, Debug)]
 struct Monkey {
-    items: Vec<usize>,
-    op: Op,
-    operand: Operand,
+    op: Expr,
     test_divisible_by: usize,
     if_true: usize,
     if_false: usize,
-    num_inspections: usize,
 }
 
-#[derive(Clone, Debug)]
-enum Op {
-    Add,
-    Mul,
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Expr {
+    Old,
+    Num(usize),
+    Add(Box<Expr>, Box<Expr>),
+    Sub(Box<Expr>, Box<Expr>),
+    Mul(Box<Expr>, Box<Expr>),
+    Div(Box<Expr>, Box<Expr>),
 }
 
-#[derive(Clone, Debug)]
-enum Operand {
-    Imm(usize),
-    Old,
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum EvalError {
+    Overflow,
+    Underflow,
+    DivisionByZero,
+    // Division can't be done on worry levels that are only known modulo something, and neither can
+    // subtraction, since whether it goes below zero depends on the actual level.
+    NotModular,
+}
+
+impl fmt::Display for EvalError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EvalError::Overflow => write!(f, "worry level overflowed"),
+            EvalError::Underflow => write!(f, "worry level went below zero"),
+            EvalError::DivisionByZero => write!(f, "division by zero"),
+            EvalError::NotModular => write!(f, "operation can't be evaluated modulo the monkeys' divisors"),
+        }
+    }
+}
+
+impl std::error::Error for EvalError {}
+
+impl Expr {
+    fn eval(&self, old: usize) -> Result<usize, EvalError> {
+        match self {
+            Expr::Old => Ok(old),
+            Expr::Num(n) => Ok(*n),
+            Expr::Add(l, r) => l.eval(old)?.checked_add(r.eval(old)?).ok_or(EvalError::Overflow),
+            Expr::Sub(l, r) => l.eval(old)?.checked_sub(r.eval(old)?).ok_or(EvalError::Underflow),
+            Expr::Mul(l, r) => l.eval(old)?.checked_mul(r.eval(old)?).ok_or(EvalError::Overflow),
+            Expr::Div(l, r) => l.eval(old)?.checked_div(r.eval(old)?).ok_or(EvalError::DivisionByZero),
+        }
+    }
+
+    // Evaluates with every intermediate result reduced modulo `modulus`.
+    fn eval_mod(&self, old: usize, modulus: usize) -> Result<usize, EvalError> {
+        let m = modulus as u128;
+        let result = match self {
+            Expr::Old => old as u128 % m,
+            Expr::Num(n) => *n as u128 % m,
+            Expr::Add(l, r) => (l.eval_mod(old, modulus)? as u128 + r.eval_mod(old, modulus)? as u128) % m,
+            Expr::Mul(l, r) => (l.eval_mod(old, modulus)? as u128 * r.eval_mod(old, modulus)? as u128) % m,
+            Expr::Sub(_, _) | Expr::Div(_, _) => return Err(EvalError::NotModular),
+        };
+        Ok(result as usize)
+    }
+
+    fn is_modular(&self) -> bool {
+        match self {
+            Expr::Old | Expr::Num(_) => true,
+            Expr::Add(l, r) | Expr::Mul(l, r) => l.is_modular() && r.is_modular(),
+            Expr::Sub(_, _) | Expr::Div(_, _) => false,
+        }
+    }
+}
+
+fn expr_factor(input: &str) -> IResult<&str, Expr> {
+    preceded(multispace0, alt((
+        value(Expr::Old, tag("old")),
+        map_res(digit1, |n: &str| n.parse().map(Expr::Num)),
+        delimited(char('('), expr, preceded(multispace0, char(')'))),
+    )))(input)
+}
+
+fn expr_binary<'a>(
+    operand: fn(&'a str) -> IResult<&'a str, Expr>,
+    operators: &'static str,
+) -> impl FnMut(&'a str) -> IResult<&'a str, Expr> {
+    move |input| {
+        let (input, first) = operand(input)?;
+        fold_many0(
+            pair(preceded(multispace0, one_of(operators)), operand),
+            move || first.clone(),
+            |l, (op, r)| match op {
+                '+' => Expr::Add(Box::new(l), Box::new(r)),
+                '-' => Expr::Sub(Box::new(l), Box::new(r)),
+                '*' => Expr::Mul(Box::new(l), Box::new(r)),
+                _ => Expr::Div(Box::new(l), Box::new(r)),
+            }
+        )(input)
+    }
+}
+
+fn expr_term(input: &str) -> IResult<&str, Expr> {
+    expr_binary(expr_factor, "*/")(input)
+}
+
+fn expr(input: &str) -> IResult<&str, Expr> {
+    expr_binary(expr_term, "+-")(input)
+}
+
+fn parse_operation(input: &str) -> Expr {
+    let (_, op) = all_consuming(delimited(
+        pair(tag("Operation: new ="), multispace0),
+        expr,
+        multispace0
+    ))(input.trim()).unwrap_or_else(|e| panic!("invalid operation `{}`: {}", input.trim(), e));
+    op
+}
+
+// Little-endian base 2^32 limbs without trailing zeros, so zero has no limbs at all.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+struct BigUint {
+    limbs: Vec<u32>,
+}
+
+impl BigUint {
+    fn is_zero(&self) -> bool {
+        self.limbs.is_empty()
+    }
+
+    fn normalize(mut self) -> Self {
+        while self.limbs.last() == Some(&0) {
+            self.limbs.pop();
+        }
+        self
+    }
+
+    fn bits(&self) -> usize {
+        self.limbs.last().map_or(0, |top| self.limbs.len() * 32 - top.leading_zeros() as usize)
+    }
+
+    fn bit(&self, i: usize) -> bool {
+        self.limbs.get(i / 32).is_some_and(|limb| limb >> (i % 32) & 1 == 1)
+    }
+
+    fn add(&self, other: &BigUint) -> BigUint {
+        let mut limbs = Vec::with_capacity(self.limbs.len().max(other.limbs.len()) + 1);
+        let mut carry = 0u64;
+        for i in 0..self.limbs.len().max(other.limbs.len()) {
+            let sum = *self.limbs.get(i).unwrap_or(&0) as u64 + *other.limbs.get(i).unwrap_or(&0) as u64 + carry;
+            limbs.push(sum as u32);
+            carry = sum >> 32;
+        }
+        limbs.push(carry as u32);
+        BigUint { limbs }.normalize()
+    }
+
+    fn checked_sub(&self, other: &BigUint) -> Option<BigUint> {
+        if *self < *other {
+            return None;
+        }
+        let mut limbs = Vec::with_capacity(self.limbs.len());
+        let mut borrow = 0i64;
+        for i in 0..self.limbs.len() {
+            let mut diff = self.limbs[i] as i64 - *other.limbs.get(i).unwrap_or(&0) as i64 - borrow;
+            borrow = 0;
+            if diff < 0 {
+                diff += 1 << 32;
+                borrow = 1;
+            }
+            limbs.push(diff as u32);
+        }
+        Some(BigUint { limbs }.normalize())
+    }
+
+    fn mul(&self, other: &BigUint) -> BigUint {
+        if self.is_zero() || other.is_zero() {
+            return BigUint::default();
+        }
+        let mut limbs = vec![0u32; self.limbs.len() + other.limbs.len()];
+        for (i, &a) in self.limbs.iter().enumerate() {
+            let mut carry = 0u64;
+            for (j, &b) in other.limbs.iter().enumerate() {
+                let product = a as u64 * b as u64 + limbs[i + j] as u64 + carry;
+                limbs[i + j] = product as u32;
+                carry = product >> 32;
+            }
+            limbs[i + other.limbs.len()] = carry as u32;
+        }
+        BigUint { limbs }.normalize()
+    }
+
+    fn div_small(&self, divisor: u32) -> (BigUint, u32) {
+        let mut limbs = vec![0u32; self.limbs.len()];
+        let mut rem = 0u64;
+        for i in (0..self.limbs.len()).rev() {
+            let acc = rem << 32 | self.limbs[i] as u64;
+            limbs[i] = (acc / divisor as u64) as u32;
+            rem = acc % divisor as u64;
+        }
+        (BigUint { limbs }.normalize(), rem as u32)
+    }
+
+    // Plain shift-and-subtract long division, fast enough for the odd `old / old` experiment.
+    fn checked_div(&self, other: &BigUint) -> Option<BigUint> {
+        match other.limbs.len() {
+            0 => None,
+            1 => Some(self.div_small(other.limbs[0]).0),
+            _ => {
+                let mut quotient = vec![0u32; self.limbs.len()];
+                let mut rem = BigUint::default();
+                for i in (0..self.bits()).rev() {
+                    rem = rem.add(&rem);
+                    if self.bit(i) {
+                        rem = rem.add(&BigUint::from(1));
+                    }
+                    if rem >= *other {
+                        rem = rem.checked_sub(other).unwrap();
+                        quotient[i / 32] |= 1 << (i % 32);
+                    }
+                }
+                Some(BigUint { limbs: quotient }.normalize())
+            }
+        }
+    }
+
+    fn rem_usize(&self, divisor: usize) -> usize {
+        self.limbs.iter().rev().fold(0u128, |rem, &limb| (rem << 32 | limb as u128) % divisor as u128) as usize
+    }
+}
+
+impl From<usize> for BigUint {
+    fn from(n: usize) -> Self {
+        BigUint { limbs: vec![n as u32, (n as u64 >> 32) as u32] }.normalize()
+    }
+}
+
+impl PartialOrd for BigUint {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for BigUint {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.limbs.len().cmp(&other.limbs.len()).then_with(|| self.limbs.iter().rev().cmp(other.limbs.iter().rev()))
+    }
+}
+
+impl fmt::Display for BigUint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_zero() {
+            return write!(f, "0");
+        }
+        let mut chunks = vec![];
+        let mut n = self.clone();
+        while !n.is_zero() {
+            let (q, r) = n.div_small(1_000_000_000);
+            chunks.push(r);
+            n = q;
+        }
+        write!(f, "{}", chunks.pop().unwrap())?;
+        for chunk in chunks.iter().rev() {
+            write!(f, "{:09}", chunk)?;
+        }
+        Ok(())
+    }
+}
+
+impl Expr {
+    fn eval_big(&self, old: &BigUint) -> Result<BigUint, EvalError> {
+        match self {
+            Expr::Old => Ok(old.clone()),
+            Expr::Num(n) => Ok(BigUint::from(*n)),
+            Expr::Add(l, r) => Ok(l.eval_big(old)?.add(&r.eval_big(old)?)),
+            Expr::Sub(l, r) => l.eval_big(old)?.checked_sub(&r.eval_big(old)?).ok_or(EvalError::Underflow),
+            Expr::Mul(l, r) => Ok(l.eval_big(old)?.mul(&r.eval_big(old)?)),
+            Expr::Div(l, r) => l.eval_big(old)?.checked_div(&r.eval_big(old)?).ok_or(EvalError::DivisionByZero),
+        }
+    }
+}
+
+// How a worry level is represented while the monkeys throw it around.
+trait Worry: Clone + fmt::Debug {
+    fn from_level(level: usize, divisors: &[usize]) -> Self;
+
+    // Checked for every monkey before a round starts, so a representation that can't handle an
+    // operation fails before any item moves.
+    fn check(op: &Expr, divider: usize) -> Result<(), EvalError> {
+        if divider == 1 && !op.is_modular() {
+            return Err(EvalError::NotModular);
+        }
+        Ok(())
+    }
+
+    fn inspect(&self, op: &Expr, divider: usize, modulus: usize) -> Result<Self, EvalError>;
+
+    fn is_divisible(&self, monkey_id: usize, divisor: usize) -> bool;
+}
+
+// Plain numbers, kept modulo the LCM of the divisors unless they're being divided.
+impl Worry for usize {
+    fn from_level(level: usize, _divisors: &[usize]) -> Self {
+        level
+    }
+
+    fn inspect(&self, op: &Expr, divider: usize, modulus: usize) -> Result<Self, EvalError> {
+        if divider == 1 {
+            op.eval_mod(*self, modulus)
+        } else {
+            Ok(op.eval(*self)? / divider)
+        }
+    }
+
+    fn is_divisible(&self, _monkey_id: usize, divisor: usize) -> bool {
+        self.is_multiple_of(divisor)
+    }
+}
+
+// Exact worry levels, however large they get.
+impl Worry for BigUint {
+    fn from_level(level: usize, _divisors: &[usize]) -> Self {
+        BigUint::from(level)
+    }
+
+    fn check(_op: &Expr, _divider: usize) -> Result<(), EvalError> {
+        Ok(())
+    }
+
+    fn inspect(&self, op: &Expr, divider: usize, _modulus: usize) -> Result<Self, EvalError> {
+        let level = op.eval_big(self)?;
+        Ok(if divider == 1 { level } else { level.checked_div(&BigUint::from(divider)).ok_or(EvalError::DivisionByZero)? })
+    }
+
+    fn is_divisible(&self, _monkey_id: usize, divisor: usize) -> bool {
+        self.rem_usize(divisor) == 0
+    }
+}
+
+// One residue per monkey, each modulo that monkey's divisor. Smaller numbers than a single residue
+// modulo the LCM, but just as unable to divide.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+struct Residues(Vec<(usize, usize)>);
+
+impl Worry for Residues {
+    fn from_level(level: usize, divisors: &[usize]) -> Self {
+        Residues(divisors.iter().map(|&divisor| (level % divisor, divisor)).collect())
+    }
+
+    fn check(op: &Expr, divider: usize) -> Result<(), EvalError> {
+        if divider != 1 || !op.is_modular() {
+            return Err(EvalError::NotModular);
+        }
+        Ok(())
+    }
+
+    fn inspect(&self, op: &Expr, _divider: usize, _modulus: usize) -> Result<Self, EvalError> {
+        self.0.iter().map(|&(residue, divisor)| Ok((op.eval_mod(residue, divisor)?, divisor))).collect::<Result<_, _>>().map(Residues)
+    }
+
+    fn is_divisible(&self, monkey_id: usize, _divisor: usize) -> bool {
+        self.0[monkey_id].0 == 0
+    }
 }
 
 #[derive(Debug)]
-struct State {
+struct State<W = usize> {
     monkeys: Vec<Monkey>,
+    // Each monkey's queue, reserved large enough for every item so rounds never allocate.
+    items: Vec<Vec<W>>,
+    num_inspections: Vec<usize>,
+    // Holds the queue of the monkey whose turn it is.
+    scratch: Vec<W>,
     round: usize,
+    // Every monkey only looks at worry levels modulo its own divisor, so they can all be kept modulo
+    // the least common multiple of the divisors without changing where any item ends up.
+    modulus: usize,
+}
+
+fn gcd(a: usize, b: usize) -> usize {
+    if b == 0 { a } else { gcd(b, a % b) }
+}
+
+fn lcm(a: usize, b: usize) -> usize {
+    a / gcd(a, b) * b
 }
 
 fn parse(input: &str) -> State {
     let mut monkeys = vec![];
+    let mut items = vec![];
     for monkey_input in input.split("\n\n") {
         let mut lines = monkey_input.lines();
 
-        let _monkey_id = usize::from_str_radix(lines.next().unwrap().trim().strip_prefix("Monkey ").unwrap().strip_suffix(":").unwrap(), 10).unwrap();
-        let items = lines.next().unwrap().trim().strip_prefix("Starting items: ").unwrap().split(",").map(|item_id| {
-            usize::from_str_radix(item_id.trim(), 10).unwrap()
-        }).collect_vec();
-        let mut operation_parts = lines.next().unwrap().trim().strip_prefix("Operation: new = old ").unwrap().split(" ");
-
-        let op = operation_parts.next().unwrap();
-        let op = match op {
-            "+" => Op::Add,
-            "*" => Op::Mul,
-            _ => panic!("wtf")
-        };
-        let operand = operation_parts.next().unwrap();
-        let operand = match operand {
-            "old" => Operand::Old,
-            _ => Operand::Imm(usize::from_str_radix(operand, 10).unwrap())
-        };
+        let _monkey_id = lines.next().unwrap().trim().strip_prefix("Monkey ").unwrap().strip_suffix(':').unwrap().parse::<usize>().unwrap();
+        items.push(lines.next().unwrap().trim().strip_prefix("Starting items: ").unwrap().split(',').map(|item_id| {
+            item_id.trim().parse().unwrap()
+        }).collect_vec());
+        let op = parse_operation(lines.next().unwrap());
 
-        let test_divisible_by = usize::from_str_radix(lines.next().unwrap().trim().strip_prefix("Test: divisible by ").unwrap(), 10).unwrap();
-        let if_true = usize::from_str_radix(lines.next().unwrap().trim().strip_prefix("If true: throw to monkey ").unwrap(), 10).unwrap();
-        let if_false = usize::from_str_radix(lines.next().unwrap().trim().strip_prefix("If false: throw to monkey ").unwrap(), 10).unwrap();
+        let test_divisible_by = lines.next().unwrap().trim().strip_prefix("Test: divisible by ").unwrap().parse().unwrap();
+        let if_true = lines.next().unwrap().trim().strip_prefix("If true: throw to monkey ").unwrap().parse().unwrap();
+        let if_false = lines.next().unwrap().trim().strip_prefix("If false: throw to monkey ").unwrap().parse().unwrap();
 
         monkeys.push(Monkey {
             //monkey_id,
-            items,
             op,
-            operand,
             test_divisible_by,
             if_true,
             if_false,
-            num_inspections: 0
         })
     }
 
-    State {
-        monkeys,
-        round: 0
+    State::new(monkeys, items)
+}
+
+impl<W> State<W> {
+    fn new(monkeys: Vec<Monkey>, items: Vec<Vec<W>>) -> Self {
+        let num_items = items.iter().map(|items| items.len()).sum();
+        let items = items.into_iter().map(|mut items| {
+            items.reserve_exact(num_items - items.len());
+            items
+        }).collect();
+        let modulus = monkeys.iter().map(|monkey| monkey.test_divisible_by).fold(1, lcm);
+        State {
+            num_inspections: vec![0; monkeys.len()],
+            monkeys,
+            items,
+            scratch: Vec::with_capacity(num_items),
+            round: 0,
+            modulus
+        }
+    }
+}
+
+impl State {
+    fn with_worry<W: Worry>(self) -> State<W> {
+        let divisors = self.monkeys.iter().map(|monkey| monkey.test_divisible_by).collect_vec();
+        let items = self.items.iter().map(|items| items.iter().map(|&level| W::from_level(level, &divisors)).collect()).collect();
+        State {
+            num_inspections: self.num_inspections,
+            round: self.round,
+            ..State::new(self.monkeys, items)
+        }
+    }
+}
+
+// The rounds a single item goes through, up to the point where its (monkey, worry level) at the start
+// of a round repeats or enough rounds have been simulated. Items never affect each other, so every
+// item can be followed on its own.
+#[derive(Debug)]
+struct ItemCycle {
+    // Where the item is at the start of each round.
+    states: Vec<(usize, usize)>,
+    // The monkeys that inspected the item during each round, as a bit set. Within a round an item
+    // only ever moves on to later monkeys, so no monkey sees it twice.
+    visits: Vec<u64>,
+    // Where the cycle starts and how long it is, unless the rounds ran out first.
+    cycle: Option<(usize, usize)>,
+}
+
+impl ItemCycle {
+    fn at(&self, rounds: usize, num_monkeys: usize) -> ((usize, usize), Vec<usize>) {
+        let (offset, laps) = match self.cycle {
+            Some((start, len)) if rounds >= self.states.len() => (start + (rounds - start) % len, (rounds - start) / len),
+            _ => (rounds, 0),
+        };
+        let mut inspections = vec![0; num_monkeys];
+        let mut count = |visits: &[u64], times: usize| {
+            for visit in visits {
+                for (monkey_id, n) in inspections.iter_mut().enumerate() {
+                    *n += (visit >> monkey_id & 1) as usize * times;
+                }
+            }
+        };
+        count(&self.visits[..offset], 1);
+        if let Some((start, len)) = self.cycle.filter(|_| laps > 0) {
+            count(&self.visits[start..start + len], laps);
+        }
+        (self.states[offset], inspections)
     }
 }
 
 impl State {
-    fn round(&mut self, divider: usize) {
-        self.round += 1;
+    fn item_cycle(&self, monkey_id: usize, level: usize, rounds: usize) -> Result<ItemCycle, EvalError> {
+        let mut seen = HashMap::new();
+        let mut states = vec![];
+        let mut visits = vec![];
+        let mut state = (monkey_id, level % self.modulus);
+        loop {
+            if let Some(&cycle_start) = seen.get(&state) {
+                return Ok(ItemCycle { cycle: Some((cycle_start, states.len() - cycle_start)), states, visits });
+            }
+            seen.insert(state, states.len());
+            states.push(state);
+            if states.len() > rounds {
+                return Ok(ItemCycle { cycle: None, states, visits });
+            }
+
+            // Items thrown to a later monkey get inspected again within the same round.
+            let mut visit = 0u64;
+            let (mut monkey_id, mut level) = state;
+            loop {
+                let monkey = &self.monkeys[monkey_id];
+                visit |= 1 << monkey_id;
+                level = monkey.op.eval_mod(level, self.modulus)?;
+                let target = if level.is_multiple_of(monkey.test_divisible_by) { monkey.if_true } else { monkey.if_false };
+                let next_round = target <= monkey_id;
+                monkey_id = target;
+                if next_round {
+                    break;
+                }
+            }
+            visits.push(visit);
+            state = (monkey_id, level);
+        }
+    }
+
+    // Same as running `round(1)` that many times, but never simulating an item for longer than its
+    // cycle, so the time doesn't grow with the number of rounds. The items do end up in a different
+    // order within each monkey's queue.
+    fn fast_forward(&mut self, rounds: usize) -> Result<(), EvalError> {
+        assert!(self.monkeys.len() <= 64, "too many monkeys to fast forward");
+        if !self.monkeys.iter().all(|monkey| monkey.op.is_modular()) {
+            return Err(EvalError::NotModular);
+        }
+
+        let mut cycles: HashMap<(usize, usize), ItemCycle> = HashMap::new();
+        let mut items = vec![vec![]; self.monkeys.len()];
+        let mut counts = vec![0; self.monkeys.len()];
         for monkey_id in 0..self.monkeys.len() {
-            let items = std::mem::replace(&mut self.monkeys[monkey_id].items, vec![]);
-            let num_items = items.len();
-            for item in items {
-                let monkey = self.monkeys[monkey_id].clone();
-                let old_worry_level = item;
-                let new_worry_level = match (&monkey.op, &monkey.operand) {
-                    (Op::Add, Operand::Imm(val)) => old_worry_level + *val,
-                    (Op::Add, Operand::Old) => old_worry_level * 2,
-                    (Op::Mul, Operand::Imm(val)) => old_worry_level * *val,
-                    (Op::Mul, Operand::Old) => old_worry_level * old_worry_level,
-                } / divider;
-                let new_worry_level = new_worry_level % 9_699_690;
-                if new_worry_level % monkey.test_divisible_by == 0 {
-                    self.monkeys[monkey.if_true].items.push(new_worry_level);
-                } else {
-                    self.monkeys[monkey.if_false].items.push(new_worry_level);
+            for &level in &self.items[monkey_id] {
+                let start = (monkey_id, level % self.modulus);
+                let cycle = match cycles.entry(start) {
+                    Entry::Occupied(entry) => entry.into_mut(),
+                    Entry::Vacant(entry) => entry.insert(self.item_cycle(monkey_id, level, rounds)?),
+                };
+                let ((end_monkey, end_level), inspections) = cycle.at(rounds, self.monkeys.len());
+                items[end_monkey].push(end_level);
+                for (count, n) in counts.iter_mut().zip(inspections) {
+                    *count += n;
                 }
             }
+        }
+        for (monkey_id, (items, count)) in items.into_iter().zip(counts).enumerate() {
+            self.items[monkey_id].clear();
+            self.items[monkey_id].extend(items);
+            self.num_inspections[monkey_id] += count;
+        }
+        self.round += rounds;
+        Ok(())
+    }
+}
+
+impl<W: Worry> State<W> {
+    fn round(&mut self, divider: usize) -> Result<(), EvalError> {
+        self.round_with(divider, |_, _| {})
+    }
 
-            self.monkeys[monkey_id].num_inspections += num_items;
+    // Calls `on_throw(from, to)` for every item thrown.
+    fn round_with(&mut self, divider: usize, mut on_throw: impl FnMut(usize, usize)) -> Result<(), EvalError> {
+        for monkey in &self.monkeys {
+            W::check(&monkey.op, divider)?;
         }
+
+        // If an inspection fails, the round stops there: the failing item and the rest of that monkey's
+        // queue stay put and only finished inspections count, though earlier throws aren't undone.
+        for (monkey_id, monkey) in self.monkeys.iter().enumerate() {
+            // Swapping keeps both buffers' capacity around for the next turn.
+            std::mem::swap(&mut self.items[monkey_id], &mut self.scratch);
+            for (i, item) in self.scratch.iter().enumerate() {
+                let new_worry_level = match item.inspect(&monkey.op, divider, self.modulus) {
+                    Ok(level) => level,
+                    Err(e) => {
+                        self.num_inspections[monkey_id] += i;
+                        self.items[monkey_id].splice(0..0, self.scratch.drain(i..));
+                        self.scratch.clear();
+                        return Err(e);
+                    }
+                };
+                let target = if new_worry_level.is_divisible(monkey_id, monkey.test_divisible_by) { monkey.if_true } else { monkey.if_false };
+                on_throw(monkey_id, target);
+                self.items[target].push(new_worry_level);
+            }
+            self.num_inspections[monkey_id] += self.scratch.len();
+            self.scratch.clear();
+        }
+        self.round += 1;
+        Ok(())
     }
 
-    fn monkey_business(&self) -> usize {
-        let top = self.monkeys.iter().map(|m| m.num_inspections).sorted().rev().take(2).collect_vec();
-        top[0] * top[1]
+    fn monkey_business(&self) -> u128 {
+        let top = self.num_inspections.iter().sorted().rev().take(2).collect_vec();
+        *top[0] as u128 * *top[1] as u128
     }
 }
 
-fn main() {
-    let mut state = parse(include_str!("day11.txt"));
-    for _ in 0..20 {
-        state.round(3);
+#[derive(Debug, Default)]
+struct History {
+    // Inspections by each monkey, per round.
+    inspections: Vec<Vec<usize>>,
+    // How many items each monkey threw to each other monkey, over all rounds.
+    throws: Vec<Vec<usize>>,
+}
+
+impl History {
+    fn record<W: Worry>(state: &mut State<W>, rounds: usize, divider: usize) -> Result<History, EvalError> {
+        let n = state.monkeys.len();
+        let mut history = History { inspections: vec![], throws: vec![vec![0; n]; n] };
+        for _ in 0..rounds {
+            let mut inspections = vec![0; n];
+            let throws = &mut history.throws;
+            state.round_with(divider, |from, to| {
+                inspections[from] += 1;
+                throws[from][to] += 1;
+            })?;
+            history.inspections.push(inspections);
+        }
+        Ok(history)
     }
-    println!("monkey business: {}", state.monkey_business());
 
-    let mut state = parse(include_str!("day11.txt"));
-    for _ in 0..10000 {
-        state.round(1);
+    fn to_csv(&self) -> String {
+        let mut csv = String::from("round");
+        for monkey_id in 0..self.throws.len() {
+            csv += &format!(",monkey{}", monkey_id);
+        }
+        csv.push('\n');
+        for (round, inspections) in self.inspections.iter().enumerate() {
+            csv += &format!("{},{}\n", round + 1, inspections.iter().join(","));
+        }
+        csv
+    }
+
+    fn throw_graph(&self) -> DiGraph<String, usize> {
+        let mut graph = DiGraph::new();
+        let nodes = (0..self.throws.len()).map(|monkey_id| graph.add_node(format!("Monkey {}", monkey_id))).collect_vec();
+        for (from, throws) in self.throws.iter().enumerate() {
+            for (to, &count) in throws.iter().enumerate() {
+                if count > 0 {
+                    graph.add_edge(nodes[from], nodes[to], count);
+                }
+            }
+        }
+        graph
+    }
+
+    fn to_dot(&self) -> String {
+        format!("{}", Dot::new(&self.throw_graph()))
     }
-    println!("more monkey business: {}", state.monkey_business());
+}
+
+fn run<W: Worry>(input: &str, rounds: usize, divider: usize) -> Result<u128, EvalError> {
+    let mut state = parse(input).with_worry::<W>();
+    for _ in 0..rounds {
+        state.round(divider)?;
+    }
+    Ok(state.monkey_business())
+}
+
+fn report(label: &str, result: Result<u128, EvalError>) {
+    match result {
+        Ok(business) => println!("{}: {}", label, business),
+        Err(e) => println!("{}: {}", label, e),
+    }
+}
+
+fn main() {
+    let input = include_str!("day11.txt");
+    let mut args = std::env::args().skip(1);
+    let mode = args.next().unwrap_or_default();
+    let rounds = args.next().map(|rounds| rounds.parse::<usize>().unwrap());
+    if mode == "dot" || mode == "history" {
+        let history = History::record(&mut parse(input), 20, 3).unwrap();
+        print!("{}", if mode == "dot" { history.to_dot() } else { history.to_csv() });
+        return;
+    }
+    // Without the modulus every `old * old` doubles the number of digits, so exact worry levels only
+    // last a few dozen rounds.
+    if mode == "bignum" {
+        assert!(rounds.is_some_and(|rounds| rounds <= 64), "bignum mode needs a round count of at most 64, e.g. `day11 bignum 30`");
+    }
+    let rounds = rounds.unwrap_or(10000);
+
+    report("monkey business", match mode.as_str() {
+        "bignum" => run::<BigUint>(input, 20, 3),
+        "residues" => run::<Residues>(input, 20, 3),
+        _ => run::<usize>(input, 20, 3),
+    });
+    report("more monkey business", match mode.as_str() {
+        "bignum" => run::<BigUint>(input, rounds, 1),
+        "residues" => run::<Residues>(input, rounds, 1),
+        "cycles" => {
+            let mut state = parse(input);
+            state.fast_forward(rounds).map(|_| state.monkey_business())
+        }
+        _ => run::<usize>(input, rounds, 1),
+    });
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
-    #[test]
-    fn test() {
-        let input = r#"Monkey 0:
+    const EXAMPLE: &str = r#"Monkey 0:
   Starting items: 79, 98
   Operation: new = old * 19
   Test: divisible by 23
@@ -154,11 +741,220 @@ Monkey 3:
   Test: divisible by 17
     If true: throw to monkey 0
     If false: throw to monkey 1"#;
+
+    #[test]
+    fn test() {
+        let input = EXAMPLE;
         let mut state = parse(input);
+        assert_eq!(state.modulus, 23 * 19 * 13 * 17);
         for _ in 0..20 {
-            state.round();
+            state.round(3).unwrap();
+        }
+        assert_eq!(state.monkey_business(), 10605);
+
+        let mut state = parse(input);
+        for _ in 0..10000 {
+            state.round(1).unwrap();
+        }
+        assert_eq!(state.num_inspections, vec![52166, 47830, 1938, 52013]);
+        assert_eq!(state.monkey_business(), 2713310158);
+    }
+
+    #[test]
+    fn test_rounds_dont_allocate() {
+        let mut state = parse(include_str!("day11.txt"));
+        // Queues trade buffers with the scratch space, but no new buffers show up.
+        let buffers = |state: &State| state.items.iter().chain([&state.scratch]).map(|items| (items.as_ptr(), items.capacity())).sorted().collect_vec();
+        let before = buffers(&state);
+        for _ in 0..10000 {
+            state.round(1).unwrap();
+        }
+        assert_eq!(buffers(&state), before);
+        assert_eq!(state.monkey_business(), 15117269860);
+    }
+
+    #[test]
+    fn test_expressions() {
+        let (_, op) = expr("old * old + 3").unwrap();
+        assert_eq!(op.eval(4), Ok(19));
+        let (_, op) = expr("(old + 2) * 5").unwrap();
+        assert_eq!(op, Expr::Mul(Box::new(Expr::Add(Box::new(Expr::Old), Box::new(Expr::Num(2)))), Box::new(Expr::Num(5))));
+        assert_eq!(op.eval(1), Ok(15));
+        assert_eq!(op.eval_mod(1, 7), Ok(1));
+
+        let op = parse_operation("  Operation: new = 100 - old * 2 - 1");
+        assert_eq!(op.eval(10), Ok(79));
+        assert_eq!(op.eval(60), Err(EvalError::Underflow));
+        assert!(!op.is_modular());
+        assert_eq!(op.eval_mod(10, 7), Err(EvalError::NotModular));
+
+        let op = parse_operation("Operation: new = old / (old - old)");
+        assert_eq!(op.eval(3), Err(EvalError::DivisionByZero));
+        assert!(!op.is_modular());
+        assert_eq!(op.eval_mod(3, 7), Err(EvalError::NotModular));
+        assert_eq!(parse_operation("Operation: new = old * 19"), Expr::Mul(Box::new(Expr::Old), Box::new(Expr::Num(19))));
+    }
+
+    #[test]
+    fn test_division_needs_divider() {
+        let input = r#"Monkey 0:
+  Starting items: 10, 20
+  Operation: new = old / 2 + 7
+  Test: divisible by 2
+    If true: throw to monkey 1
+    If false: throw to monkey 1
+
+Monkey 1:
+  Starting items: 3
+  Operation: new = (old + 1) * 2
+  Test: divisible by 3
+    If true: throw to monkey 0
+    If false: throw to monkey 0"#;
+        let mut state = parse(input);
+        assert_eq!(state.round(1), Err(EvalError::NotModular));
+        assert_eq!(state.round(3), Ok(()));
+        assert_eq!(state.items[0], vec![2, 3, 4]);
+    }
+
+    #[test]
+    fn test_subtraction_is_not_modular() {
+        let input = r#"Monkey 0:
+  Starting items: 5, 50
+  Operation: new = old - 10
+  Test: divisible by 2
+    If true: throw to monkey 1
+    If false: throw to monkey 1
+
+Monkey 1:
+  Starting items: 7
+  Operation: new = old * 2
+  Test: divisible by 3
+    If true: throw to monkey 0
+    If false: throw to monkey 0"#;
+        // Every representation refuses to let worry levels go below zero, whether or not it can tell.
+        assert_eq!(run::<usize>(input, 5, 1), Err(EvalError::NotModular));
+        assert_eq!(run::<Residues>(input, 5, 1), Err(EvalError::NotModular));
+        assert_eq!(run::<BigUint>(input, 5, 1), Err(EvalError::Underflow));
+        assert_eq!(parse(input).fast_forward(5), Err(EvalError::NotModular));
+        assert_eq!(run::<usize>(input, 1, 3), run::<BigUint>(input, 1, 3));
+    }
+
+    #[test]
+    fn test_failed_round_keeps_items() {
+        let input = r#"Monkey 0:
+  Starting items: 200, 10, 300
+  Operation: new = old - 100
+  Test: divisible by 2
+    If true: throw to monkey 1
+    If false: throw to monkey 1
+
+Monkey 1:
+  Starting items: 5
+  Operation: new = old + 1
+  Test: divisible by 3
+    If true: throw to monkey 0
+    If false: throw to monkey 0"#;
+        let mut state = parse(input);
+        assert_eq!(state.round(3), Err(EvalError::Underflow));
+        assert_eq!(state.round, 0);
+        assert_eq!(state.items.iter().map(|items| items.len()).sum::<usize>(), 4);
+        assert_eq!(state.items, vec![vec![10, 300], vec![5, 33]]);
+        assert_eq!(state.num_inspections, vec![1, 0]);
+    }
+
+    #[test]
+    fn test_worry_representations() {
+        let a = BigUint::from(usize::MAX);
+        let square = a.mul(&a);
+        assert_eq!(square.to_string(), "340282366920938463426481119284349108225");
+        assert_eq!(square.checked_div(&a), Some(a.clone()));
+        assert_eq!(square.add(&BigUint::from(1)).checked_sub(&square), Some(BigUint::from(1)));
+        assert_eq!(a.checked_sub(&square), None);
+        assert_eq!(square.rem_usize(1000), 225);
+        assert_eq!(BigUint::default().to_string(), "0");
+
+        let input = include_str!("day11.txt");
+        assert_eq!(run::<BigUint>(input, 20, 3), Ok(55944));
+        assert_eq!(run::<Residues>(input, 1000, 1), run::<usize>(input, 1000, 1));
+        assert_eq!(run::<Residues>(input, 20, 3), Err(EvalError::NotModular));
+
+        // Without any modulus, the real input squares its way far beyond 64 bits.
+        let mut state = parse(input).with_worry::<BigUint>();
+        for _ in 0..10 {
+            state.round(1).unwrap();
+        }
+        assert!(state.items.iter().flatten().any(|level| level.bits() > 64));
+    }
+
+    #[test]
+    fn test_history() {
+        let mut state = parse(EXAMPLE);
+        let history = History::record(&mut state, 1, 3).unwrap();
+        assert_eq!(history.inspections, vec![vec![2, 4, 3, 5]]);
+        assert_eq!(history.throws, vec![vec![0, 0, 0, 2], vec![4, 0, 0, 0], vec![0, 1, 0, 2], vec![0, 5, 0, 0]]);
+        assert!(history.to_dot().contains("3 -> 1 [ label = \"5\" ]"));
+
+        let history2 = History::record(&mut state, 19, 3).unwrap();
+        let totals = (0..4).map(|monkey_id| {
+            history.inspections.iter().chain(&history2.inspections).map(|round| round[monkey_id]).sum::<usize>()
+        }).collect_vec();
+        assert_eq!(totals, vec![101, 95, 7, 105]);
+        assert_eq!(history2.to_csv().lines().nth(1), Some("1,4,6,1,5"));
+    }
+
+    #[test]
+    fn test_fast_forward() {
+        let mut state = parse(include_str!("day11.txt"));
+        state.fast_forward(10000).unwrap();
+        assert_eq!(state.round, 10000);
+        assert_eq!(state.monkey_business(), 15117269860);
+
+        let mut stepped = parse(include_str!("day11.txt"));
+        let mut skipped = parse(include_str!("day11.txt"));
+        for _ in 0..1234 {
+            stepped.round(1).unwrap();
+        }
+        skipped.fast_forward(1000).unwrap();
+        skipped.fast_forward(234).unwrap();
+        skipped.fast_forward(0).unwrap();
+        assert_eq!(stepped.num_inspections, skipped.num_inspections);
+        for (a, b) in stepped.items.iter().zip(&skipped.items) {
+            assert_eq!(a.iter().sorted().collect_vec(), b.iter().sorted().collect_vec());
+        }
+
+        let mut state = parse(include_str!("day11.txt"));
+        let num_items = state.items.iter().map(|items| items.len()).sum::<usize>();
+        state.fast_forward(1_000_000_000).unwrap();
+        let total = state.num_inspections.iter().sum::<usize>();
+        assert!(total >= num_items * 1_000_000_000 && total <= num_items * state.monkeys.len() * 1_000_000_000);
+
+        // Only as many rounds get simulated as were asked for, even when the cycle is much longer.
+        let input = r#"Monkey 0:
+  Starting items: 1, 2
+  Operation: new = old * 3
+  Test: divisible by 1009
+    If true: throw to monkey 1
+    If false: throw to monkey 1
+
+Monkey 1:
+  Starting items: 4
+  Operation: new = old + 1
+  Test: divisible by 1013
+    If true: throw to monkey 0
+    If false: throw to monkey 0"#;
+        let state = parse(input);
+        let cycle = state.item_cycle(0, 1, 3).unwrap();
+        assert_eq!(cycle.states.len(), 4);
+        assert_eq!(cycle.cycle, None);
+        assert!(state.item_cycle(0, 1, 1_000_000_000).unwrap().states.len() > 1000);
+
+        let mut stepped = parse(input);
+        let mut skipped = parse(input);
+        for _ in 0..3 {
+            stepped.round(1).unwrap();
         }
-        println!("{:#?}", state);
-        println!("monkey business: {}", state.monkey_business());
+        skipped.fast_forward(3).unwrap();
+        assert_eq!(stepped.num_inspections, skipped.num_inspections);
+        assert_eq!(stepped.items.iter().map(|items| items.iter().sorted().collect_vec()).collect_vec(), skipped.items.iter().map(|items| items.iter().sorted().collect_vec()).collect_vec());
     }
 }
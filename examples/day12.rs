@@ -21,7 +21,7 @@ fn parse(input: &str, include_all_starts: bool) -> (Graph<IVec2, (), Directed>,
                 'E' => {
                     25
                 },
-                _ => c as u8 - 'a' as u8
+                _ => c as u8 - b'a'
             };
             row.push(height);
 
@@ -71,17 +71,40 @@ fn parse(input: &str, include_all_starts: bool) -> (Graph<IVec2, (), Directed>,
     (g, starts, end.unwrap())
 }
 
+fn route(graph: &Graph<IVec2, (), Directed>, start: NodeIndex, end: NodeIndex) -> Option<Vec<IVec2>> {
+    let (_, path) = astar(graph, start, |finish| finish == end, |_| 1, |_| 0)?;
+    Some(path.into_iter().map(|nx| graph[nx]).collect())
+}
+
+// Draws the route the way the puzzle does, with every step pointing at the next one.
+fn render_route(input: &str, route: &[IVec2]) -> String {
+    let mut grid = input.lines().map(|line| vec!['.'; line.len()]).collect_vec();
+    for (from, to) in route.iter().tuple_windows() {
+        grid[from.y as usize][from.x as usize] = match *to - *from {
+            IVec2 { x: 1, y: 0 } => '>',
+            IVec2 { x: -1, y: 0 } => '<',
+            IVec2 { x: 0, y: -1 } => '^',
+            IVec2 { x: 0, y: 1 } => 'v',
+            step => panic!("route steps from {} to {}, which are not adjacent ({})", from, to, step),
+        };
+    }
+    if let Some(end) = route.last() {
+        grid[end.y as usize][end.x as usize] = 'E';
+    }
+    grid.into_iter().map(|row| row.into_iter().collect::<String>()).join("\n")
+}
+
 fn main() {
-    let (graph, starts, end) = parse(include_str!("day12.txt"), false);
-    let path = astar(&graph, *starts.first().unwrap(), |finish| finish == end, |_| 1, |_| 0);
-    println!("num steps: {}", path.unwrap().0);
+    let input = include_str!("day12.txt");
+    let (graph, starts, end) = parse(input, false);
+    let path = route(&graph, *starts.first().unwrap(), end).unwrap();
+    println!("num steps: {}", path.len() - 1);
+    println!("{}", render_route(input, &path));
 
-    let (graph, starts, end) = parse(include_str!("day12.txt"), true);
-    let min_steps = starts.into_iter().filter_map(|start| {
-        let path = astar(&graph, start, |finish| finish == end, |_| 1, |_| 0);
-        path.map(|(len, _)| len)
-    }).min();
-    println!("num steps: {}", min_steps.unwrap());
+    let (graph, starts, end) = parse(input, true);
+    let shortest = starts.into_iter().filter_map(|start| route(&graph, start, end)).min_by_key(|path| path.len()).unwrap();
+    println!("num steps: {}", shortest.len() - 1);
+    println!("{}", render_route(input, &shortest));
 }
 
 #[cfg(test)]
@@ -95,8 +118,34 @@ abcryxxl
 accszExk
 acctuvwj
 abdefghi";
-        let (graph, start, end) = parse(input);
-        let path = astar(&graph, start, |finish| finish == end, |_| 1, |_| 0);
+        let (graph, starts, end) = parse(input, false);
+        let path = astar(&graph, starts[0], |finish| finish == end, |_| 1, |_| 0);
         assert_eq!(path.unwrap().0, 31);
     }
+
+    #[test]
+    fn test_route() {
+        let input = r"Sabqponm
+abcryxxl
+accszExk
+acctuvwj
+abdefghi";
+        let (graph, starts, end) = parse(input, false);
+        let path = route(&graph, starts[0], end).unwrap();
+        assert_eq!(path.len(), 32);
+        assert_eq!(path[0], IVec2::new(0, 0));
+        assert_eq!(path[31], IVec2::new(5, 2));
+        assert!(path.iter().tuple_windows().all(|(a, b)| { let step = (*b - *a).abs(); step.x + step.y == 1 }));
+        assert_eq!(render_route(input, &path), r"v..v<<<<
+>v.vv<<^
+.v.v>E^^
+.>v>>>^^
+..>>>>>^");
+    }
+
+    #[test]
+    #[should_panic(expected = "not adjacent")]
+    fn test_render_rejects_jumps() {
+        render_route("abc\ndef", &[IVec2::new(0, 0), IVec2::new(2, 1)]);
+    }
 }
@@ -1,6 +1,16 @@
 #![allow(dead_code)]
 
+use std::collections::HashSet;
+use std::{fmt, fs, io};
+use std::path::Path;
 use itertools::Itertools;
+use nom::branch::alt;
+use nom::bytes::complete::take_while_m_n;
+use nom::character::complete::{char, digit1, multispace0, none_of};
+use nom::combinator::{map, map_opt, map_res, value};
+use nom::IResult;
+use nom::multi::{many0, separated_list0};
+use nom::sequence::{delimited, preceded, separated_pair};
 
 #[derive(Debug)]
 enum Child {
@@ -8,63 +18,280 @@ enum Child {
     File(File)
 }
 
+impl Child {
+    fn name(&self) -> &str {
+        match self {
+            Child::Directory(dir) => &dir.name,
+            Child::File(file) => &file.name
+        }
+    }
+
+    fn set_name(&mut self, name: impl Into<String>) {
+        match self {
+            Child::Directory(dir) => dir.name = name.into(),
+            Child::File(file) => file.name = name.into()
+        }
+    }
+
+    fn size(&self) -> usize {
+        match self {
+            Child::Directory(dir) => dir.size(),
+            Child::File(file) => file.size
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Entry<'a> {
+    Directory(&'a Directory),
+    File(&'a File)
+}
+
+impl<'a> Entry<'a> {
+    fn name(&self) -> &'a str {
+        match self {
+            Entry::Directory(dir) => &dir.name,
+            Entry::File(file) => &file.name
+        }
+    }
+
+    fn size(&self) -> usize {
+        match self {
+            Entry::Directory(dir) => dir.size(),
+            Entry::File(file) => file.size
+        }
+    }
+
+    fn as_dir(&self) -> Option<&'a Directory> {
+        match self {
+            Entry::Directory(dir) => Some(dir),
+            Entry::File(_) => None
+        }
+    }
+}
+
+impl<'a> From<&'a Child> for Entry<'a> {
+    fn from(child: &'a Child) -> Self {
+        match child {
+            Child::Directory(dir) => Entry::Directory(dir),
+            Child::File(file) => Entry::File(file)
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum FsError {
+    NotFound(String),
+    NotADirectory(String),
+    AlreadyExists(String),
+    InvalidPath(String),
+}
+
+impl fmt::Display for FsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FsError::NotFound(path) => write!(f, "no such file or directory: {}", path),
+            FsError::NotADirectory(path) => write!(f, "not a directory: {}", path),
+            FsError::AlreadyExists(path) => write!(f, "already exists: {}", path),
+            FsError::InvalidPath(path) => write!(f, "invalid path: {}", path),
+        }
+    }
+}
+
+impl std::error::Error for FsError {}
+
+// Splits a path into its segments, resolving `.` and `..`. Paths are always
+// taken relative to the directory they're resolved against, so a leading `/`
+// is optional.
+fn split_path(path: &str) -> Vec<&str> {
+    let mut segments = vec![];
+    for segment in path.split('/') {
+        match segment {
+            "" | "." => (),
+            ".." => {
+                segments.pop();
+            },
+            _ => segments.push(segment)
+        }
+    }
+    segments
+}
+
+fn join_path(segments: &[&str]) -> String {
+    format!("/{}", segments.join("/"))
+}
+
 #[derive(Debug)]
 struct Directory {
     name: String,
     children: Vec<Child>,
+    size: usize,
 }
 
 impl Directory {
     fn new(name: impl Into<String>) -> Self {
         Self {
             name: name.into(),
-            children: vec![]
+            children: vec![],
+            size: 0
         }
     }
 
     fn size(&self) -> usize {
-        self.children.iter()
-            .map(|child| {
-                match child {
-                    Child::Directory(dir) => {
-                        dir.size()
-                    },
-                    Child::File(f) => {
-                        f.size
-                    }
-                }
-            })
-            .sum()
+        self.size
+    }
+
+    fn child(&self, name: &str) -> Option<&Child> {
+        self.children.iter().find(|child| child.name() == name)
     }
 
-    fn add_child(&mut self, child: Child) {
+    fn add_child(&mut self, child: Child) -> Result<(), FsError> {
+        if self.child(child.name()).is_some() {
+            return Err(FsError::AlreadyExists(child.name().to_string()));
+        }
+        self.size += child.size();
         self.children.push(child);
+        Ok(())
+    }
+
+    fn cd(&self, target: &str) -> Option<&Directory> {
+        match self.child(target) {
+            Some(Child::Directory(dir)) => Some(dir),
+            _ => None
+        }
+    }
+
+    fn resolve(&self, path: &str) -> Result<Entry<'_>, FsError> {
+        let segments = split_path(path);
+        let mut cur = Entry::Directory(self);
+        for (i, segment) in segments.iter().enumerate() {
+            let dir = cur.as_dir().ok_or_else(|| FsError::NotADirectory(join_path(&segments[..i])))?;
+            cur = dir.child(segment)
+                .map(Entry::from)
+                .ok_or_else(|| FsError::NotFound(join_path(&segments[..=i])))?;
+        }
+        Ok(cur)
+    }
+
+    fn walk(&self) -> Walk<'_> {
+        Walk {
+            stack: vec![("/".to_string(), Entry::Directory(self))]
+        }
     }
 
-    fn find(&mut self, path: &[&str]) -> &mut Directory {
-        println!("trying to find: {:?}", &path);
+    fn mkdir_p(&mut self, path: &str) -> Result<(), FsError> {
+        let segments = split_path(path);
         let mut cur = self;
-        for i in 0..path.len() {
-            cur = cur.cd(path[i]).unwrap();
+        for (i, segment) in segments.iter().enumerate() {
+            if cur.child(segment).is_none() {
+                cur.children.push(Child::Directory(Directory::new(*segment)));
+            }
+            cur = match cur.children.iter_mut().find(|child| child.name() == *segment) {
+                Some(Child::Directory(dir)) => dir,
+                _ => return Err(FsError::NotADirectory(join_path(&segments[..=i])))
+            };
         }
-        cur
+        Ok(())
     }
 
-    fn cd(&mut self, target: impl Into<String>) -> Option<&mut Directory> {
-        let target = target.into();
+    fn add_file(&mut self, path: &str, size: usize) -> Result<(), FsError> {
+        let segments = split_path(path);
+        let (name, parent) = segments.split_last().ok_or_else(|| FsError::InvalidPath(path.to_string()))?;
+        self.insert(parent, Child::File(File {
+            name: name.to_string(),
+            size
+        }))
+    }
 
-        for child in &mut self.children {
-            match child {
-                Child::Directory(dir) => {
-                    if dir.name == target {
-                        return Some(dir);
-                    }
-                },
-                Child::File(_) => ()
+    fn remove(&mut self, path: &str) -> Result<Child, FsError> {
+        let segments = split_path(path);
+        let (name, parent) = segments.split_last().ok_or_else(|| FsError::InvalidPath(path.to_string()))?;
+        let dir = self.dir_mut(parent)?;
+        let idx = dir.children.iter()
+            .position(|child| child.name() == *name)
+            .ok_or_else(|| FsError::NotFound(join_path(&segments)))?;
+        let child = dir.children.remove(idx);
+        self.adjust_sizes(parent, -(child.size() as isize));
+        Ok(child)
+    }
+
+    fn rename(&mut self, from: &str, to: &str) -> Result<(), FsError> {
+        let from_segments = split_path(from);
+        let to_segments = split_path(to);
+        let (to_name, to_parent) = to_segments.split_last().ok_or_else(|| FsError::InvalidPath(to.to_string()))?;
+        if from_segments.is_empty() || to_segments.starts_with(&from_segments) {
+            return Err(FsError::InvalidPath(to.to_string()));
+        }
+
+        match self.resolve(&join_path(to_parent))? {
+            Entry::Directory(dir) if dir.child(to_name).is_some() => return Err(FsError::AlreadyExists(join_path(&to_segments))),
+            Entry::Directory(_) => (),
+            Entry::File(_) => return Err(FsError::NotADirectory(join_path(to_parent)))
+        }
+
+        let mut child = self.remove(from)?;
+        child.set_name(*to_name);
+        self.insert(to_parent, child)
+    }
+
+    fn insert(&mut self, parent: &[&str], child: Child) -> Result<(), FsError> {
+        let size = child.size();
+        let dir = self.dir_mut(parent)?;
+        if dir.child(child.name()).is_some() {
+            return Err(FsError::AlreadyExists(join_path(&[parent, &[child.name()]].concat())));
+        }
+        dir.children.push(child);
+        self.adjust_sizes(parent, size as isize);
+        Ok(())
+    }
+
+    // Doesn't touch any cached sizes, callers are expected to follow up with `adjust_sizes`.
+    fn dir_mut(&mut self, segments: &[&str]) -> Result<&mut Directory, FsError> {
+        let mut cur = self;
+        for (i, segment) in segments.iter().enumerate() {
+            cur = match cur.children.iter_mut().find(|child| child.name() == *segment) {
+                Some(Child::Directory(dir)) => dir,
+                Some(Child::File(_)) => return Err(FsError::NotADirectory(join_path(&segments[..=i]))),
+                None => return Err(FsError::NotFound(join_path(&segments[..=i])))
+            };
+        }
+        Ok(cur)
+    }
+
+    // Applies `delta` to this directory and every directory along `segments`.
+    fn adjust_sizes(&mut self, segments: &[&str], delta: isize) {
+        let mut cur = self;
+        let mut segments = segments.iter();
+        loop {
+            cur.size = (cur.size as isize + delta) as usize;
+            match segments.next() {
+                Some(segment) => cur = cur.dir_mut(&[segment]).unwrap(),
+                None => break
             }
         }
+    }
+}
+
+struct Walk<'a> {
+    stack: Vec<(String, Entry<'a>)>
+}
 
-        None
+impl<'a> Iterator for Walk<'a> {
+    type Item = (String, Entry<'a>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (path, entry) = self.stack.pop()?;
+        if let Entry::Directory(dir) = entry {
+            for child in dir.children.iter().rev() {
+                let child_path = if path == "/" {
+                    format!("/{}", child.name())
+                } else {
+                    format!("{}/{}", path, child.name())
+                };
+                self.stack.push((child_path, Entry::from(child)));
+            }
+        }
+        Some((path, entry))
     }
 }
 
@@ -74,60 +301,221 @@ struct File {
     size: usize
 }
 
-fn parse(input: &str) -> Directory {
-    let mut root = Directory::new("/");
-    let mut cwd = vec![];
-
-    let mut lines = input.lines().peekable();
-    loop {
-        match lines.next() {
-            Some(instruction) => {
-                if !instruction.starts_with('$') {
-                    panic!();
-                }
-                let mut split = instruction.split_whitespace();
-                let _ = split.next();
-                match split.next() {
-                    Some("cd") => {
-                        let arg = split.next().unwrap();
-                        if arg == "/" {
-                            cwd = vec![];
-                        } else if arg == ".." {
-                            cwd.pop();
-                        } else {
-                            cwd.push(arg);
-                        }
-                    },
-                    Some("ls") => {
-                        let directory = root.find(&cwd);
-                        loop {
-                            let next = lines.peek();
-                            if next.is_some() && !next.unwrap().starts_with('$') {
-                                let line = lines.next().unwrap();
-                                let mut split = line.split_whitespace();
-                                let ty_or_size = split.next().unwrap();
-                                let name = split.next().unwrap();
-                                if ty_or_size == "dir" {
-                                    directory.add_child(Child::Directory(Directory::new(name)));
-                                } else {
-                                    directory.add_child(Child::File(File {
-                                        name: name.to_string(),
-                                        size: usize::from_str_radix(ty_or_size, 10).unwrap(),
-                                    }));
-                                }
-                            } else {
-                                break;
-                            }
+#[derive(Debug, PartialEq, Eq)]
+enum TranscriptError {
+    UnexpectedOutput { line: usize },
+    UnknownCommand { line: usize, command: String },
+    MissingArgument { line: usize, command: String },
+    InvalidListing { line: usize, output: String },
+    InvalidSize { line: usize, size: String },
+}
+
+impl fmt::Display for TranscriptError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TranscriptError::UnexpectedOutput { line } => write!(f, "line {}: output without a command", line),
+            TranscriptError::UnknownCommand { line, command } => write!(f, "line {}: unknown command `{}`", line, command),
+            TranscriptError::MissingArgument { line, command } => write!(f, "line {}: missing argument to `{}`", line, command),
+            TranscriptError::InvalidListing { line, output } => write!(f, "line {}: invalid ls output `{}`", line, output),
+            TranscriptError::InvalidSize { line, size } => write!(f, "line {}: invalid file size `{}`", line, size),
+        }
+    }
+}
+
+impl std::error::Error for TranscriptError {}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Warning {
+    // A directory was listed again, but the output doesn't match what we already know about it.
+    InconsistentListing { line: usize, path: String },
+    // A directory was entered or listed before it showed up in any listing.
+    UnlistedDirectory { line: usize, path: String },
+    PwdMismatch { line: usize, expected: String, actual: String },
+    Failed { line: usize, error: FsError },
+}
+
+struct Shell {
+    root: Directory,
+    cwd: String,
+    listed: HashSet<String>,
+    warnings: Vec<Warning>,
+}
+
+impl Shell {
+    fn new() -> Self {
+        Self {
+            root: Directory::new("/"),
+            cwd: "/".to_string(),
+            listed: HashSet::new(),
+            warnings: vec![]
+        }
+    }
+
+    fn run(&mut self, input: &str) -> Result<(), TranscriptError> {
+        let mut lines = input.lines()
+            .enumerate()
+            .map(|(idx, line)| (idx + 1, line))
+            .peekable();
+
+        while let Some((line, instruction)) = lines.next() {
+            let command = instruction.strip_prefix('$').ok_or(TranscriptError::UnexpectedOutput { line })?;
+            let mut output = vec![];
+            while let Some(next) = lines.next_if(|(_, next)| !next.starts_with('$')) {
+                output.push(next);
+            }
+
+            let args = command.split_whitespace().collect_vec();
+            let arg = |idx: usize| args.get(idx).copied().ok_or_else(|| TranscriptError::MissingArgument {
+                line,
+                command: args.first().copied().unwrap_or_default().to_string()
+            });
+            match args.first().copied() {
+                Some("cd") => self.cd(line, args.get(1).copied().unwrap_or("/")),
+                Some("ls") => self.ls(line, args.get(1).copied().unwrap_or("."), &output)?,
+                Some("pwd") => {
+                    if let Some((_, actual)) = output.first() {
+                        if *actual != self.cwd {
+                            self.warnings.push(Warning::PwdMismatch { line, expected: self.cwd.clone(), actual: actual.to_string() });
                         }
                     }
-                    _ => panic!()
-                }
+                },
+                Some("mkdir") => {
+                    let path = self.absolute(arg(1)?);
+                    let result = match self.root.resolve(&path) {
+                        Ok(_) => Err(FsError::AlreadyExists(path)),
+                        Err(_) => self.root.mkdir_p(&path)
+                    };
+                    self.check(line, result);
+                },
+                Some("rm") => {
+                    let path = self.absolute(arg(1)?);
+                    let result = self.root.remove(&path);
+                    if result.is_ok() {
+                        self.listed.retain(|listed| *listed != path && !listed.starts_with(&format!("{}/", path)));
+                    }
+                    self.check(line, result);
+                },
+                Some("touch") => {
+                    let size = arg(1)?;
+                    let size = size.parse().map_err(|_| TranscriptError::InvalidSize { line, size: size.to_string() })?;
+                    let path = self.absolute(arg(2)?);
+                    let result = self.write_file(&path, size);
+                    self.check(line, result);
+                },
+                _ => return Err(TranscriptError::UnknownCommand { line, command: command.trim().to_string() })
+            }
+        }
+
+        Ok(())
+    }
+
+    fn absolute(&self, path: &str) -> String {
+        if path.starts_with('/') {
+            join_path(&split_path(path))
+        } else {
+            join_path(&split_path(&format!("{}/{}", self.cwd, path)))
+        }
+    }
+
+    fn check<T>(&mut self, line: usize, result: Result<T, FsError>) {
+        if let Err(error) = result {
+            self.warnings.push(Warning::Failed { line, error });
+        }
+    }
+
+    // Makes sure `path` exists as a directory, creating it if no listing has mentioned it so far.
+    fn enter(&mut self, line: usize, path: &str) -> bool {
+        match self.root.resolve(path) {
+            Ok(Entry::Directory(_)) => true,
+            Ok(Entry::File(_)) => {
+                self.warnings.push(Warning::Failed { line, error: FsError::NotADirectory(path.to_string()) });
+                false
+            },
+            Err(FsError::NotFound(_)) => {
+                self.warnings.push(Warning::UnlistedDirectory { line, path: path.to_string() });
+                let result = self.root.mkdir_p(path);
+                let ok = result.is_ok();
+                self.check(line, result);
+                ok
             },
-            None => break
+            Err(error) => {
+                self.warnings.push(Warning::Failed { line, error });
+                false
+            }
         }
     }
 
-    root
+    fn cd(&mut self, line: usize, target: &str) {
+        let path = self.absolute(target);
+        if self.enter(line, &path) {
+            self.cwd = path;
+        }
+    }
+
+    fn ls(&mut self, line: usize, target: &str, output: &[(usize, &str)]) -> Result<(), TranscriptError> {
+        let mut entries = vec![];
+        for (line, listing) in output {
+            let invalid = || TranscriptError::InvalidListing { line: *line, output: listing.to_string() };
+            let (ty_or_size, name) = listing.split_once(' ').ok_or_else(invalid)?;
+            let size = match ty_or_size {
+                "dir" => None,
+                _ => Some(ty_or_size.parse::<usize>().map_err(|_| invalid())?)
+            };
+            entries.push((name, size));
+        }
+
+        let path = self.absolute(target);
+        if !self.enter(line, &path) {
+            return Ok(());
+        }
+
+        if !self.listed.insert(path.clone()) {
+            let dir = self.root.resolve(&path).unwrap().as_dir().unwrap();
+            let known = dir.children.iter()
+                .map(|child| match child {
+                    Child::Directory(dir) => (dir.name.as_str(), None),
+                    Child::File(file) => (file.name.as_str(), Some(file.size))
+                })
+                .sorted()
+                .collect_vec();
+            if known != entries.iter().copied().sorted().collect_vec() {
+                self.warnings.push(Warning::InconsistentListing { line, path: path.clone() });
+            }
+        }
+
+        for (name, size) in entries {
+            let child_path = format!("{}/{}", path, name);
+            let result = match size {
+                None => self.root.mkdir_p(&child_path),
+                Some(size) => self.write_file(&child_path, size)
+            };
+            self.check(line, result);
+        }
+
+        Ok(())
+    }
+
+    fn write_file(&mut self, path: &str, size: usize) -> Result<(), FsError> {
+        match self.root.resolve(path) {
+            Ok(Entry::File(file)) if file.size == size => return Ok(()),
+            Ok(Entry::File(_)) => {
+                self.root.remove(path)?;
+            },
+            Ok(Entry::Directory(_)) => return Err(FsError::AlreadyExists(path.to_string())),
+            Err(_) => ()
+        }
+        self.root.add_file(path, size)
+    }
+}
+
+fn interpret(input: &str) -> Result<Shell, TranscriptError> {
+    let mut shell = Shell::new();
+    shell.run(input)?;
+    Ok(shell)
+}
+
+fn parse(input: &str) -> Directory {
+    interpret(input).unwrap().root
 }
 
 fn print(root: &Directory, indent: usize) {
@@ -144,58 +532,523 @@ fn print(root: &Directory, indent: usize) {
     }
 }
 
-fn calc_small_dir_sum(root: &Directory) -> Vec<&Directory> {
-    let mut result = vec![];
+#[derive(Debug, PartialEq, Eq)]
+enum ImportError {
+    InvalidJson(String),
+    InvalidTree { line: usize },
+    Fs(FsError),
+}
 
-    for child in &root.children {
-        match &child {
-            Child::Directory(dir) => {
-                result.extend(calc_small_dir_sum(dir));
-            },
-            Child::File(_) => ()
+impl fmt::Display for ImportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ImportError::InvalidJson(message) => write!(f, "invalid json: {}", message),
+            ImportError::InvalidTree { line } => write!(f, "line {}: invalid tree listing", line),
+            ImportError::Fs(error) => error.fmt(f),
         }
     }
+}
+
+impl std::error::Error for ImportError {}
 
-    if root.size() < 100000 {
-        result.push(root);
+impl From<FsError> for ImportError {
+    fn from(error: FsError) -> Self {
+        ImportError::Fs(error)
     }
+}
 
+impl Directory {
+    fn from_fs(path: impl AsRef<Path>) -> io::Result<Directory> {
+        let path = path.as_ref();
+        let name = path.file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_else(|| "/".to_string());
+        let mut dir = Directory::new(name);
+
+        let entries = fs::read_dir(path)?
+            .collect::<io::Result<Vec<_>>>()?
+            .into_iter()
+            .sorted_by_key(|entry| entry.file_name());
+        for entry in entries {
+            // Symlinks are skipped so we can't end up in a loop, or count the same file twice.
+            let metadata = fs::symlink_metadata(entry.path())?;
+            let child = if metadata.is_dir() {
+                Child::Directory(Directory::from_fs(entry.path())?)
+            } else if metadata.is_file() {
+                Child::File(File {
+                    name: entry.file_name().to_string_lossy().to_string(),
+                    size: metadata.len() as usize
+                })
+            } else {
+                continue;
+            };
+            dir.add_child(child).map_err(io::Error::other)?;
+        }
+
+        Ok(dir)
+    }
+
+    fn to_json(&self) -> String {
+        fn child_json(child: &Child) -> String {
+            match child {
+                Child::Directory(dir) => dir.to_json(),
+                Child::File(file) => format!("{{\"name\":{},\"size\":{}}}", json_string(&file.name), file.size)
+            }
+        }
+
+        format!("{{\"name\":{},\"children\":[{}]}}", json_string(&self.name), self.children.iter().map(child_json).join(","))
+    }
+
+    fn from_json(input: &str) -> Result<Directory, ImportError> {
+        let (rest, value) = json_value(input).map_err(|e| ImportError::InvalidJson(e.to_string()))?;
+        if !rest.trim().is_empty() {
+            return Err(ImportError::InvalidJson(format!("trailing input: {}", rest.trim())));
+        }
+        match json_to_child(&value)? {
+            Child::Directory(dir) => Ok(dir),
+            Child::File(_) => Err(ImportError::InvalidJson("expected a directory at the top level".to_string()))
+        }
+    }
+
+    // Renders the directory like `tree -F`, with file sizes in parentheses.
+    fn to_tree(&self) -> String {
+        fn tree_dir(dir: &Directory, prefix: &str, out: &mut String) {
+            for (idx, child) in dir.children.iter().enumerate() {
+                let last = idx == dir.children.len() - 1;
+                let (branch, indent) = if last { ("└── ", "    ") } else { ("├── ", "│   ") };
+                match child {
+                    Child::Directory(child) => {
+                        *out += &format!("{}{}{}/\n", prefix, branch, child.name);
+                        tree_dir(child, &format!("{}{}", prefix, indent), out);
+                    },
+                    Child::File(file) => *out += &format!("{}{}{} ({})\n", prefix, branch, file.name, file.size)
+                }
+            }
+        }
+
+        let mut out = format!("{}\n", self.name);
+        tree_dir(self, "", &mut out);
+        out
+    }
+
+    fn from_tree(input: &str) -> Result<Directory, ImportError> {
+        let mut lines = input.lines().enumerate().map(|(idx, line)| (idx + 1, line));
+        let (_, name) = lines.next().ok_or(ImportError::InvalidTree { line: 1 })?;
+        let mut root = Directory::new(name);
+
+        let mut path: Vec<String> = vec![];
+        for (line, text) in lines {
+            let invalid = ImportError::InvalidTree { line };
+            let mut depth = 0;
+            let mut rest = text;
+            while let Some(stripped) = ["│   ", "    "].iter().find_map(|indent| rest.strip_prefix(indent)) {
+                rest = stripped;
+                depth += 1;
+            }
+            let entry = rest.strip_prefix("├── ").or_else(|| rest.strip_prefix("└── ")).ok_or(invalid)?;
+            if depth > path.len() {
+                return Err(ImportError::InvalidTree { line });
+            }
+            path.truncate(depth);
+
+            let parent = join_path(&path.iter().map(String::as_str).collect_vec());
+            if let Some(name) = entry.strip_suffix('/') {
+                root.mkdir_p(&format!("{}/{}", parent, name))?;
+                path.push(name.to_string());
+            } else {
+                let (name, size) = entry.strip_suffix(')')
+                    .and_then(|entry| entry.rsplit_once(" ("))
+                    .and_then(|(name, size)| size.parse().ok().map(|size| (name, size)))
+                    .ok_or(ImportError::InvalidTree { line })?;
+                root.add_file(&format!("{}/{}", parent, name), size)?;
+            }
+        }
+
+        Ok(root)
+    }
+}
+
+#[derive(Debug)]
+enum Json {
+    Object(Vec<(String, Json)>),
+    Array(Vec<Json>),
+    String(String),
+    Number(usize),
+}
+
+fn json_string(s: &str) -> String {
+    let mut out = "\"".to_string();
+    for c in s.chars() {
+        match c {
+            '"' => out += "\\\"",
+            '\\' => out += "\\\\",
+            '\n' => out += "\\n",
+            '\t' => out += "\\t",
+            c if c.is_control() => out += &format!("\\u{:04x}", c as u32),
+            c => out.push(c)
+        }
+    }
+    out.push('"');
+    out
+}
+
+fn json_string_literal(input: &str) -> IResult<&str, String> {
+    let escaped = alt((
+        value('"', char('"')),
+        value('\\', char('\\')),
+        value('/', char('/')),
+        value('\n', char('n')),
+        value('\t', char('t')),
+        value('\r', char('r')),
+        map_opt(preceded(char('u'), take_while_m_n(4, 4, |c: char| c.is_ascii_hexdigit())), |hex| {
+            u32::from_str_radix(hex, 16).ok().and_then(char::from_u32)
+        }),
+    ));
+    let (input, chars) = delimited(
+        char('"'),
+        many0(alt((none_of("\"\\"), preceded(char('\\'), escaped)))),
+        char('"')
+    )(input)?;
+    Ok((input, chars.into_iter().collect()))
+}
+
+fn json_value(input: &str) -> IResult<&str, Json> {
+    let member = separated_pair(preceded(multispace0, json_string_literal), preceded(multispace0, char(':')), json_value);
+    preceded(multispace0, alt((
+        map(delimited(char('{'), separated_list0(preceded(multispace0, char(',')), member), preceded(multispace0, char('}'))), Json::Object),
+        map(delimited(char('['), separated_list0(preceded(multispace0, char(',')), json_value), preceded(multispace0, char(']'))), Json::Array),
+        map(json_string_literal, Json::String),
+        map_res(digit1, |n: &str| n.parse().map(Json::Number)),
+    )))(input)
+}
+
+fn json_to_child(value: &Json) -> Result<Child, ImportError> {
+    let invalid = |message: &str| ImportError::InvalidJson(message.to_string());
+    let Json::Object(members) = value else {
+        return Err(invalid("expected an object"));
+    };
+    let field = |key: &str| members.iter().find(|(k, _)| k == key).map(|(_, v)| v);
+
+    let Some(Json::String(name)) = field("name") else {
+        return Err(invalid("expected a `name` string"));
+    };
+    match (field("size"), field("children")) {
+        (Some(Json::Number(size)), None) => Ok(Child::File(File {
+            name: name.clone(),
+            size: *size
+        })),
+        (None, Some(Json::Array(children))) => {
+            let mut dir = Directory::new(name.clone());
+            for child in children {
+                dir.add_child(json_to_child(child)?)?;
+            }
+            Ok(Child::Directory(dir))
+        },
+        _ => Err(invalid("expected either a `size` number or a `children` array"))
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Change {
+    AddedFile { path: String, size: usize },
+    RemovedFile { path: String, size: usize },
+    ResizedFile { path: String, old_size: usize, new_size: usize },
+    AddedDirectory { path: String, size: usize },
+    RemovedDirectory { path: String, size: usize },
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Change::AddedFile { path, size } => write!(f, "+ {} ({})", path, size),
+            Change::RemovedFile { path, size } => write!(f, "- {} ({})", path, size),
+            Change::ResizedFile { path, old_size, new_size } => write!(f, "~ {} ({} -> {})", path, old_size, new_size),
+            Change::AddedDirectory { path, size } => write!(f, "+ {}/ ({})", path, size),
+            Change::RemovedDirectory { path, size } => write!(f, "- {}/ ({})", path, size),
+        }
+    }
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+struct FsDiff {
+    changes: Vec<Change>,
+    // Every directory whose total size changed, including ones that were added or removed entirely.
+    size_deltas: Vec<(String, isize)>,
+}
+
+impl FsDiff {
+    fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    fn added(&mut self, path: String, child: &Child) {
+        self.changed_dir(&path, child, 1);
+        self.changes.push(match child {
+            Child::Directory(dir) => Change::AddedDirectory { path, size: dir.size() },
+            Child::File(file) => Change::AddedFile { path, size: file.size }
+        });
+    }
+
+    fn removed(&mut self, path: String, child: &Child) {
+        self.changed_dir(&path, child, -1);
+        self.changes.push(match child {
+            Child::Directory(dir) => Change::RemovedDirectory { path, size: dir.size() },
+            Child::File(file) => Change::RemovedFile { path, size: file.size }
+        });
+    }
+
+    fn changed_dir(&mut self, path: &str, child: &Child, sign: isize) {
+        if let Child::Directory(dir) = child {
+            for (sub_path, entry) in dir.walk() {
+                if let Some(sub_dir) = entry.as_dir().filter(|sub_dir| sub_dir.size() > 0) {
+                    let sub_path = if sub_path == "/" { path.to_string() } else { format!("{}{}", path, sub_path) };
+                    self.size_deltas.push((sub_path, sign * sub_dir.size() as isize));
+                }
+            }
+        }
+    }
+}
+
+impl fmt::Display for FsDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for change in &self.changes {
+            writeln!(f, "{}", change)?;
+        }
+        for (path, delta) in &self.size_deltas {
+            writeln!(f, "{:+}\t{}", delta, path)?;
+        }
+        Ok(())
+    }
+}
+
+fn diff(old: &Directory, new: &Directory) -> FsDiff {
+    fn diff_dirs(old: &Directory, new: &Directory, path: &str, diff: &mut FsDiff) {
+        let delta = new.size() as isize - old.size() as isize;
+        if delta != 0 {
+            diff.size_deltas.push((path.to_string(), delta));
+        }
+
+        let names = old.children.iter()
+            .chain(&new.children)
+            .map(Child::name)
+            .unique()
+            .collect_vec();
+        for name in names {
+            let child_path = format!("{}/{}", path.trim_end_matches('/'), name);
+            match (old.child(name), new.child(name)) {
+                (Some(Child::File(old)), Some(Child::File(new))) => {
+                    if old.size != new.size {
+                        diff.changes.push(Change::ResizedFile { path: child_path, old_size: old.size, new_size: new.size });
+                    }
+                },
+                (Some(Child::Directory(old)), Some(Child::Directory(new))) => diff_dirs(old, new, &child_path, diff),
+                (Some(old), Some(new)) => {
+                    diff.removed(child_path.clone(), old);
+                    diff.added(child_path, new);
+                },
+                (Some(old), None) => diff.removed(child_path, old),
+                (None, Some(new)) => diff.added(child_path, new),
+                (None, None) => unreachable!()
+            }
+        }
+    }
+
+    let mut result = FsDiff::default();
+    diff_dirs(old, new, "/", &mut result);
     result
 }
 
+fn diff_transcripts(old: &str, new: &str) -> Result<FsDiff, TranscriptError> {
+    Ok(diff(&interpret(old)?.root, &interpret(new)?.root))
+}
+
+fn calc_small_dir_sum(root: &Directory) -> Vec<&Directory> {
+    root.walk()
+        .filter_map(|(_, entry)| entry.as_dir())
+        .filter(|dir| dir.size() < 100000)
+        .collect()
+}
+
 fn calc_size_candidates(root: &Directory) -> Vec<usize> {
-    let mut candidates = vec![];
-    for child in &root.children {
-        match child {
-            Child::Directory(dir) => {
-                candidates.extend(calc_size_candidates(dir));
-            },
-            Child::File(_) => ()
+    root.walk()
+        .filter_map(|(_, entry)| entry.as_dir())
+        .map(|dir| dir.size())
+        .collect()
+}
+
+fn human_size(size: usize) -> String {
+    const UNITS: [&str; 5] = ["", "K", "M", "G", "T"];
+    let mut value = size as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+
+    // Like `du -h`, sizes are always rounded up.
+    let tenths = (value * 10.0).ceil() / 10.0;
+    if unit == 0 {
+        size.to_string()
+    } else if tenths < 10.0 {
+        format!("{:.1}{}", tenths, UNITS[unit])
+    } else {
+        format!("{}{}", value.ceil(), UNITS[unit])
+    }
+}
+
+fn du(root: &Directory, max_depth: Option<usize>) -> String {
+    fn du_dir(dir: &Directory, path: &str, depth: usize, max_depth: Option<usize>, out: &mut String) {
+        for child in &dir.children {
+            if let Child::Directory(child) = child {
+                du_dir(child, &format!("{}/{}", path.trim_end_matches('/'), child.name), depth + 1, max_depth, out);
+            }
+        }
+        if max_depth.is_none_or(|max_depth| depth <= max_depth) {
+            *out += &format!("{}\t{}\n", human_size(dir.size()), path);
         }
     }
-    candidates.push(root.size());
 
-    candidates
+    let mut out = String::new();
+    du_dir(root, "/", 0, max_depth, &mut out);
+    out
+}
+
+fn largest_dirs(root: &Directory, n: usize) -> Vec<(String, usize)> {
+    root.walk()
+        .filter_map(|(path, entry)| entry.as_dir().map(|dir| (path, dir.size())))
+        .sorted_by(|(l_path, l_size), (r_path, r_size)| r_size.cmp(l_size).then(l_path.cmp(r_path)))
+        .take(n)
+        .collect()
+}
+
+// The DP keeps one row of buckets per directory, so the bucket count shrinks as directories are added
+// to keep the table within `MAX_DP_BITS` (8 MiB). Only past a million directories does the floor of 64
+// buckets take over, and then the table grows by 8 bytes per directory.
+const MAX_DP_BUCKETS: usize = 1 << 16;
+const MIN_DP_BUCKETS: usize = 64;
+const MAX_DP_BITS: usize = 1 << 26;
+
+fn num_dp_buckets(num_dirs: usize) -> usize {
+    (MAX_DP_BITS / (num_dirs + 1)).clamp(MIN_DP_BUCKETS, MAX_DP_BUCKETS)
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Disk {
+    capacity: usize,
+    required: usize,
+}
+
+impl Disk {
+    fn space_needed(&self, root: &Directory) -> usize {
+        let unused = self.capacity.saturating_sub(root.size());
+        self.required.saturating_sub(unused)
+    }
+
+    fn smallest_dir_to_delete(&self, root: &Directory) -> Option<(String, usize)> {
+        let needed = self.space_needed(root);
+        root.walk()
+            .filter_map(|(path, entry)| entry.as_dir().map(|dir| (path, dir.size())))
+            .filter(|(_, size)| *size >= needed)
+            .min_by_key(|(_, size)| *size)
+    }
+
+    // Finds the set of directories, none of them inside another, that frees up enough space while
+    // deleting as little as possible. This is a knapsack problem, so sizes are bucketed to keep large
+    // disks tractable: the result always frees enough space, but can overshoot the optimum by up to
+    // one bucket per directory. It's exact as long as the smallest single candidate fits in as many
+    // bytes as there are buckets, see `num_dp_buckets`.
+    fn smallest_dirs_to_delete(&self, root: &Directory) -> Option<(Vec<String>, usize)> {
+        let needed = self.space_needed(root);
+        if needed == 0 {
+            return Some((vec![], 0));
+        }
+        let (single_path, single_size) = self.smallest_dir_to_delete(root)?;
+
+        // Directories in pre-order, along with the index just past their subtree. Deleting a
+        // directory means skipping straight to the end of its subtree.
+        let dirs = root.walk()
+            .filter_map(|(path, entry)| entry.as_dir().map(|dir| (path, dir.size())))
+            .collect_vec();
+        let ends = dirs.iter().enumerate()
+            .map(|(idx, (path, _))| {
+                let prefix = format!("{}/", path.trim_end_matches('/'));
+                idx + 1 + dirs[idx + 1..].iter().take_while(|(other, _)| other.starts_with(&prefix)).count()
+            })
+            .collect_vec();
+
+        let bucket = single_size.div_ceil(num_dp_buckets(dirs.len())).max(1);
+        let num_buckets = single_size / bucket + 1;
+        let words = num_buckets.div_ceil(64);
+
+        // reachable[i] has bit `n` set if deleting directories from `dirs[i..]` can free exactly `n` buckets.
+        let mut reachable = vec![vec![0u64; words]; dirs.len() + 1];
+        reachable[dirs.len()][0] = 1;
+        for idx in (0..dirs.len()).rev() {
+            let mut bits = reachable[idx + 1].clone();
+            let shift = dirs[idx].1 / bucket;
+            if shift < num_buckets {
+                for (word, src) in reachable[ends[idx]].iter().enumerate() {
+                    let dst = word * 64 + shift;
+                    if *src == 0 || dst / 64 >= words {
+                        continue;
+                    }
+                    bits[dst / 64] |= src << (dst % 64);
+                    if dst % 64 != 0 && dst / 64 + 1 < words {
+                        bits[dst / 64 + 1] |= src >> (64 - dst % 64);
+                    }
+                }
+            }
+            reachable[idx] = bits;
+        }
+
+        let is_reachable = |idx: usize, n: usize| reachable[idx][n / 64] & (1 << (n % 64)) != 0;
+        let Some(mut target) = (needed.div_ceil(bucket)..num_buckets).find(|n| is_reachable(0, *n)) else {
+            return Some((vec![single_path], single_size));
+        };
+
+        let mut paths = vec![];
+        let mut size = 0;
+        let mut idx = 0;
+        while target > 0 {
+            if is_reachable(idx + 1, target) {
+                idx += 1;
+            } else {
+                paths.push(dirs[idx].0.clone());
+                size += dirs[idx].1;
+                target -= dirs[idx].1 / bucket;
+                idx = ends[idx];
+            }
+        }
+
+        if size < single_size {
+            Some((paths, size))
+        } else {
+            Some((vec![single_path], single_size))
+        }
+    }
 }
 
 pub fn main() {
+    let mut args = std::env::args().skip(1).map(|arg| arg.parse().expect("sizes should be numbers"));
+    let disk = Disk {
+        capacity: args.next().unwrap_or(70_000_000),
+        required: args.next().unwrap_or(30_000_000),
+    };
+
     let root = parse(include_str!("day7.txt"));
-    print(&root, 0);
+    print!("{}", du(&root, Some(1)));
     println!("sum: {}", calc_small_dir_sum(&root).iter().map(|d| d.size()).sum::<usize>());
 
-    let unused = 70_000_000 - root.size();
-    println!("unused: {}", 70_000_000 - root.size());
-    let extra_space_required = 30_000_000 - (70_000_000 - root.size());
-    println!("extra_space_required: {}",extra_space_required);
-    let candidates = calc_size_candidates(&root)
-        .into_iter()
-        .filter(|size| {
-            unused + size >= 30_000_000
-        })
-        .sorted()
-        .collect_vec();
-    println!("{:#?}", candidates);
-    println!("smallest dir size: {}", candidates.first().unwrap());
+    println!("largest directories:");
+    for (path, size) in largest_dirs(&root, 5) {
+        println!("{}\t{}", human_size(size), path);
+    }
+
+    println!("extra_space_required: {}", disk.space_needed(&root));
+    let (path, size) = disk.smallest_dir_to_delete(&root).unwrap();
+    println!("smallest dir size: {} ({})", size, path);
+    let (paths, size) = disk.smallest_dirs_to_delete(&root).unwrap();
+    println!("smallest dir set size: {} ({})", size, paths.join(", "));
 }
 
 #[cfg(test)]
@@ -235,5 +1088,226 @@ $ ls
         let mut candidates = calc_size_candidates(&root);
         candidates.sort();
         println!("{:#?}", candidates);
+
+        let disk = Disk { capacity: 70_000_000, required: 30_000_000 };
+        assert_eq!(disk.smallest_dir_to_delete(&root), Some(("/d".to_string(), 24933642)));
+        assert_eq!(disk.smallest_dirs_to_delete(&root), Some((vec!["/d".to_string()], 24933642)));
+    }
+
+    #[test]
+    fn test_vfs() {
+        let mut root = Directory::new("/");
+        root.mkdir_p("/a/e").unwrap();
+        root.add_file("/a/e/i", 584).unwrap();
+        root.add_file("/a/f", 29116).unwrap();
+        root.add_file("/b.txt", 14848514).unwrap();
+        assert_eq!(root.size(), 584 + 29116 + 14848514);
+        assert_eq!(root.resolve("/a").unwrap().size(), 584 + 29116);
+        assert_eq!(root.resolve("/a/e/../f").unwrap().size(), 29116);
+        assert_eq!(root.resolve("/a/x").unwrap_err(), FsError::NotFound("/a/x".to_string()));
+        assert_eq!(root.resolve("/b.txt/x").unwrap_err(), FsError::NotADirectory("/b.txt".to_string()));
+        assert_eq!(root.add_file("/a/f", 1).unwrap_err(), FsError::AlreadyExists("/a/f".to_string()));
+
+        let paths = root.walk().map(|(path, _)| path).collect_vec();
+        assert_eq!(paths, vec!["/", "/a", "/a/e", "/a/e/i", "/a/f", "/b.txt"]);
+
+        root.rename("/a/e", "/e").unwrap();
+        assert_eq!(root.resolve("/a").unwrap().size(), 29116);
+        assert_eq!(root.resolve("/e/i").unwrap().size(), 584);
+        assert_eq!(root.rename("/e", "/e/x").unwrap_err(), FsError::InvalidPath("/e/x".to_string()));
+
+        let removed = root.remove("/a").unwrap();
+        assert_eq!(removed.size(), 29116);
+        assert_eq!(root.size(), 584 + 14848514);
+        assert_eq!(root.remove("/").unwrap_err(), FsError::InvalidPath("/".to_string()));
+    }
+
+    #[test]
+    fn test_shell() {
+        let input = r"$ cd /
+$ ls
+dir a
+100 b
+$ cd a/e
+$ pwd
+/a/e
+$ touch 10 x
+$ cd /a
+$ ls e
+10 x
+$ mkdir f/g
+$ cd ../a/f/g
+$ touch 5 y
+$ cd /
+$ ls
+dir a
+200 b
+$ rm /a/e
+$ ls a
+dir f";
+
+        let shell = interpret(input).unwrap();
+        assert_eq!(shell.cwd, "/");
+        assert_eq!(shell.root.size(), 205);
+        assert_eq!(shell.root.resolve("/a/f/g/y").unwrap().size(), 5);
+        assert_eq!(shell.warnings, vec![
+            Warning::UnlistedDirectory { line: 5, path: "/a/e".to_string() },
+            Warning::InconsistentListing { line: 16, path: "/".to_string() },
+        ]);
+
+        assert_eq!(interpret("$ cd /
+$ cat b").err(), Some(TranscriptError::UnknownCommand { line: 2, command: "cat b".to_string() }));
+        assert_eq!(interpret("dir a").err(), Some(TranscriptError::UnexpectedOutput { line: 1 }));
+        assert_eq!(interpret("$ cd /
+$ ls
+big a").err(), Some(TranscriptError::InvalidListing { line: 3, output: "big a".to_string() }));
+    }
+
+    #[test]
+    fn test_disk_usage() {
+        let mut root = Directory::new("/");
+        root.mkdir_p("/x").unwrap();
+        root.mkdir_p("/y/z").unwrap();
+        root.add_file("/x/a", 30).unwrap();
+        root.add_file("/y/b", 20).unwrap();
+        root.add_file("/y/z/c", 10).unwrap();
+        root.add_file("/d", 30).unwrap();
+
+        assert_eq!(human_size(1023), "1023");
+        assert_eq!(human_size(14848514), "15M");
+        assert_eq!(human_size(2000), "2.0K");
+        assert_eq!(du(&root, None), "30\t/x\n10\t/y/z\n30\t/y\n90\t/\n");
+        assert_eq!(du(&root, Some(0)), "90\t/\n");
+        assert_eq!(largest_dirs(&root, 2), vec![("/".to_string(), 90), ("/x".to_string(), 30)]);
+
+        let disk = Disk { capacity: 100, required: 50 };
+        assert_eq!(disk.space_needed(&root), 40);
+        assert_eq!(disk.smallest_dir_to_delete(&root), Some(("/".to_string(), 90)));
+        assert_eq!(disk.smallest_dirs_to_delete(&root), Some((vec!["/x".to_string(), "/y/z".to_string()], 40)));
+
+        let disk = Disk { capacity: 200, required: 50 };
+        assert_eq!(disk.smallest_dirs_to_delete(&root), Some((vec![], 0)));
+
+        assert_eq!(num_dp_buckets(10), MAX_DP_BUCKETS);
+        assert!(num_dp_buckets(100_000) * 100_001 <= MAX_DP_BITS);
+        assert_eq!(num_dp_buckets(10_000_000), MIN_DP_BUCKETS);
+
+        // With many directories the buckets get coarser, but enough space still gets freed.
+        let mut root = Directory::new("/");
+        for i in 0..3000 {
+            let dir = format!("/d{}/e{}", i % 1000, i / 1000);
+            root.mkdir_p(&dir).unwrap();
+            root.add_file(&format!("{}/f", dir), 1000 + i * 37).unwrap();
+        }
+        let disk = Disk { capacity: root.size() + 1_000, required: 1_500_000 };
+        let needed = disk.space_needed(&root);
+        let (paths, size) = disk.smallest_dirs_to_delete(&root).unwrap();
+        assert!(paths.len() > 1);
+        assert!(size >= needed && size <= disk.smallest_dir_to_delete(&root).unwrap().1);
+    }
+
+    #[test]
+    fn test_import_export() {
+        let mut root = Directory::new("/");
+        root.mkdir_p("/a/e").unwrap();
+        root.mkdir_p("/empty").unwrap();
+        root.add_file("/a/e/i", 584).unwrap();
+        root.add_file("/a/\"quoted\"", 10).unwrap();
+        root.add_file("/b.txt", 14848514).unwrap();
+
+        let json = root.to_json();
+        assert_eq!(json, r#"{"name":"/","children":[{"name":"a","children":[{"name":"e","children":[{"name":"i","size":584}]},{"name":"\"quoted\"","size":10}]},{"name":"empty","children":[]},{"name":"b.txt","size":14848514}]}"#);
+        let imported = Directory::from_json(&json).unwrap();
+        assert_eq!(imported.size(), root.size());
+        assert_eq!(imported.to_json(), json);
+        assert!(Directory::from_json(r#"{"name": "/", "children": [ {"name": "x"} ]}"#).is_err());
+        let spaced = Directory::from_json(r#"
+            {
+                "name" : "/" ,
+                "children" : [
+                    { "name" : "x" , "size" : 5 } ,
+                    { "name" : "y" , "children" : [ ] }
+                ]
+            }
+        "#).unwrap();
+        assert_eq!(spaced.to_json(), r#"{"name":"/","children":[{"name":"x","size":5},{"name":"y","children":[]}]}"#);
+
+        let tree = root.to_tree();
+        assert_eq!(tree, r#"/
+├── a/
+│   ├── e/
+│   │   └── i (584)
+│   └── "quoted" (10)
+├── empty/
+└── b.txt (14848514)
+"#);
+        let imported = Directory::from_tree(&tree).unwrap();
+        assert_eq!(imported.to_tree(), tree);
+        assert_eq!(imported.resolve("/a").unwrap().size(), 594);
+        assert_eq!(Directory::from_tree("/\n├── a (x)").unwrap_err(), ImportError::InvalidTree { line: 2 });
+
+        let dir = std::env::temp_dir().join(format!("day7-{}", std::process::id()));
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        fs::write(dir.join("sub/file"), [0u8; 100]).unwrap();
+        fs::write(dir.join("top"), [0u8; 20]).unwrap();
+        let imported = Directory::from_fs(&dir);
+        fs::remove_dir_all(&dir).unwrap();
+        let imported = imported.unwrap();
+        assert_eq!(imported.size(), 120);
+        assert_eq!(imported.resolve("/sub/file").unwrap().size(), 100);
+    }
+
+    #[test]
+    fn test_diff() {
+        let old = r"$ cd /
+$ ls
+dir a
+100 b
+dir c
+$ cd a
+$ ls
+10 x
+20 y
+$ cd /c
+$ ls
+dir d
+dir e
+$ cd e
+$ ls
+$ cd /c/d
+$ ls
+5 z";
+        let new = r"$ cd /
+$ ls
+dir a
+dir b
+$ cd a
+$ ls
+15 x
+20 y
+30 w
+$ cd /b
+$ ls
+1 n";
+
+        let diff = diff_transcripts(old, new).unwrap();
+        assert_eq!(diff.changes, vec![
+            Change::ResizedFile { path: "/a/x".to_string(), old_size: 10, new_size: 15 },
+            Change::AddedFile { path: "/a/w".to_string(), size: 30 },
+            Change::RemovedFile { path: "/b".to_string(), size: 100 },
+            Change::AddedDirectory { path: "/b".to_string(), size: 1 },
+            Change::RemovedDirectory { path: "/c".to_string(), size: 5 },
+        ]);
+        assert_eq!(diff.size_deltas, vec![
+            ("/".to_string(), -69),
+            ("/a".to_string(), 35),
+            ("/b".to_string(), 1),
+            ("/c".to_string(), -5),
+            ("/c/d".to_string(), -5),
+        ]);
+        // The empty `/c/e` went away too, but its size didn't change.
+        assert!(diff.size_deltas.iter().all(|(path, _)| path != "/c/e"));
+        assert_eq!(diff.to_string().lines().next(), Some("~ /a/x (10 -> 15)"));
+        assert!(diff_transcripts(old, old).unwrap().is_empty());
     }
 }
@@ -2,29 +2,95 @@ struct Matrix {
     rows: Vec<Vec<usize>>
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Encoding {
+    // One `0`-`9` per tree, like the puzzle input.
+    Digits,
+    // One `0`-`f` per tree, in either case.
+    Hex,
+    // One `a`-`z` per tree, with `a` being the lowest.
+    Letters,
+    // Whitespace-separated numbers of any length.
+    Numbers,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum ParseError {
+    InvalidHeight { line: usize, column: usize, found: String },
+    RaggedRow { line: usize, expected: usize, found: usize },
+}
+
+impl std::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ParseError::InvalidHeight { line, column, found } => write!(f, "line {}, column {}: invalid height `{}`", line, column, found),
+            ParseError::RaggedRow { line, expected, found } => write!(f, "line {}: expected {} trees, found {}", line, expected, found),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
 impl Matrix {
     pub fn from(input: &str) -> Self {
-        let rows = input.lines()
-            .map(|line| {
-                line.chars()
-                    .map(|c| usize::from_str_radix(&c.to_string(), 10).unwrap())
-                    .collect()
-            })
-            .collect();
-
-        Self {
-            rows
+        Self::parse(input, Encoding::Digits).unwrap()
+    }
+
+    fn parse(input: &str, encoding: Encoding) -> Result<Self, ParseError> {
+        let mut rows: Vec<Vec<usize>> = vec![];
+        for (line_idx, line) in input.lines().enumerate() {
+            let line_no = line_idx + 1;
+            let invalid = |column: usize, found: &str| ParseError::InvalidHeight { line: line_no, column, found: found.to_string() };
+
+            let row = match encoding {
+                Encoding::Numbers => {
+                    let mut row = vec![];
+                    let mut column = 1;
+                    for token in line.split(char::is_whitespace) {
+                        if !token.is_empty() {
+                            row.push(token.parse().map_err(|_| invalid(column, token))?);
+                        }
+                        column += token.chars().count() + 1;
+                    }
+                    row
+                },
+                _ => line.chars()
+                    .enumerate()
+                    .map(|(idx, c)| {
+                        let height = match encoding {
+                            Encoding::Digits => c.to_digit(10),
+                            Encoding::Hex => c.to_digit(16),
+                            Encoding::Letters => c.is_ascii_lowercase().then(|| c as u32 - 'a' as u32),
+                            Encoding::Numbers => unreachable!()
+                        };
+                        height.map(|height| height as usize).ok_or_else(|| invalid(idx + 1, &c.to_string()))
+                    })
+                    .collect::<Result<_, _>>()?
+            };
+
+            if let Some(first) = rows.first() {
+                if row.len() != first.len() {
+                    return Err(ParseError::RaggedRow { line: line_no, expected: first.len(), found: row.len() });
+                }
+            }
+            rows.push(row);
         }
+
+        Ok(Self {
+            rows
+        })
     }
 
     fn width(&self) -> usize {
-        self.rows.first().unwrap().len()
+        self.rows.first().map_or(0, |row| row.len())
     }
 
     fn height(&self) -> usize {
         self.rows.len()
     }
 
+    // Walking a ray from a single tree is only kept around to check `view_from` against.
+    #[cfg(test)]
     fn vis_from(&self, mut pos: (i32, i32), offset: (i32, i32)) -> bool {
         let val = self.rows[pos.1 as usize][pos.0 as usize];
         loop {
@@ -39,6 +105,7 @@ impl Matrix {
         }
     }
 
+    #[cfg(test)]
     fn score_from(&self, mut pos: (i32, i32), offset: (i32, i32)) -> usize {
         let val = self.rows[pos.1 as usize][pos.0 as usize];
         let mut cnt = 0;
@@ -58,53 +125,328 @@ impl Matrix {
         cnt
     }
 
+    fn in_bounds(&self, pos: (i32, i32)) -> bool {
+        pos.0 >= 0 && pos.1 >= 0 && pos.0 < self.width() as i32 && pos.1 < self.height() as i32
+    }
+
+    // Looks in the direction of `offset` from every tree at once. Each line of trees is swept starting
+    // from the edge `offset` points at, keeping a stack of the trees seen so far with strictly decreasing
+    // heights, so the top of the stack is always the closest tree that blocks the view. That makes this
+    // O(width * height) instead of walking a ray from every tree.
+    fn view_from(&self, offset: (i32, i32)) -> View {
+        assert_ne!(offset, (0, 0));
+
+        let mut view = View {
+            visible: vec![vec![false; self.width()]; self.height()],
+            distance: vec![vec![0; self.width()]; self.height()],
+        };
+        let mut stack: Vec<(usize, usize)> = vec![];
+        for y in 0..self.height() as i32 {
+            for x in 0..self.width() as i32 {
+                if self.in_bounds((x + offset.0, y + offset.1)) {
+                    continue;
+                }
+
+                stack.clear();
+                let mut pos = (x, y);
+                let mut step = 0;
+                while self.in_bounds(pos) {
+                    let (x, y) = (pos.0 as usize, pos.1 as usize);
+                    let height = self.rows[y][x];
+                    while stack.last().is_some_and(|(_, other)| *other < height) {
+                        stack.pop();
+                    }
+                    match stack.last() {
+                        Some((blocked_at, _)) => view.distance[y][x] = step - blocked_at,
+                        None => {
+                            view.visible[y][x] = true;
+                            view.distance[y][x] = step;
+                        }
+                    }
+                    stack.push((step, height));
+
+                    pos.0 -= offset.0;
+                    pos.1 -= offset.1;
+                    step += 1;
+                }
+            }
+        }
+        view
+    }
+
+    fn views(&self) -> Views {
+        self.views_towards(&Directions::Axes)
+    }
+
+    fn views_towards(&self, directions: &Directions) -> Views {
+        let mut views = Views {
+            heights: self.rows.clone(),
+            directions: vec![],
+            visible: vec![vec![false; self.width()]; self.height()],
+            scores: vec![vec![1; self.width()]; self.height()],
+        };
+        for offset in directions.offsets() {
+            let view = self.view_from(offset);
+            for y in 0..self.height() {
+                for x in 0..self.width() {
+                    views.visible[y][x] |= view.visible[y][x];
+                    views.scores[y][x] *= view.distance[y][x];
+                }
+            }
+            views.directions.push((direction_name(offset), view));
+        }
+        views
+    }
+
+    #[cfg(test)]
     fn scenic_score(&self, x: usize, y: usize) -> usize {
         self.score_from((x as i32, y as i32), (-1, 0)) *
             self.score_from((x as i32, y as i32), (1, 0)) *
             self.score_from((x as i32, y as i32), (0, -1)) *
             self.score_from((x as i32, y as i32), (0, 1))
     }
+
+    #[cfg(test)]
+    fn scenic_score_towards(&self, x: usize, y: usize, directions: &Directions) -> usize {
+        directions.offsets().into_iter()
+            .map(|offset| self.score_from((x as i32, y as i32), offset))
+            .product()
+    }
 }
 
-fn num_visible(input: &str) -> usize {
-    let mtx = Matrix::from(input);
+// What can be seen looking in a single direction from every tree.
+struct View {
+    visible: Vec<Vec<bool>>,
+    distance: Vec<Vec<usize>>,
+}
 
-    let mut cnt = 0;
-    for y in 0..(mtx.height() as i32) {
-        for x in 0..(mtx.width() as i32) {
-            if mtx.vis_from((x, y), (-1, 0)) || mtx.vis_from((x, y), (1, 0)) || mtx.vis_from((x, y), (0, -1)) || mtx.vis_from((x, y), (0, 1)) {
-                cnt += 1;
-            }
+#[derive(Clone, Debug)]
+enum Directions {
+    Axes,
+    AxesAndDiagonals,
+    KnightMoves,
+    Custom(Vec<(i32, i32)>),
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct ZeroOffsetError;
+
+impl std::fmt::Display for ZeroOffsetError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "can't look in direction (0, 0)")
+    }
+}
+
+impl std::error::Error for ZeroOffsetError {}
+
+impl Directions {
+    // Custom directions should be built through here, looking nowhere would never reach an edge.
+    fn custom(offsets: Vec<(i32, i32)>) -> Result<Self, ZeroOffsetError> {
+        if offsets.contains(&(0, 0)) {
+            return Err(ZeroOffsetError);
         }
+        Ok(Directions::Custom(offsets))
     }
 
-    cnt
+    fn offsets(&self) -> Vec<(i32, i32)> {
+        match self {
+            Directions::Axes => vec![(-1, 0), (1, 0), (0, -1), (0, 1)],
+            Directions::AxesAndDiagonals => vec![(-1, 0), (1, 0), (0, -1), (0, 1), (-1, -1), (1, -1), (-1, 1), (1, 1)],
+            Directions::KnightMoves => vec![(-2, -1), (-1, -2), (1, -2), (2, -1), (2, 1), (1, 2), (-1, 2), (-2, 1)],
+            Directions::Custom(offsets) => offsets.clone()
+        }
+    }
 }
 
-fn max_score(input: &str) -> usize {
-    let mtx = Matrix::from(input);
-
-    let mut score = 0;
-    for y in 0..mtx.height() {
-        for x in 0..mtx.width() {
-            let new_score = mtx.scenic_score(x, y);
-            if new_score > score {
-                score = new_score;
+fn direction_name(offset: (i32, i32)) -> String {
+    match offset {
+        (-1, 0) => "left".to_string(),
+        (1, 0) => "right".to_string(),
+        (0, -1) => "up".to_string(),
+        (0, 1) => "down".to_string(),
+        (-1, -1) => "up_left".to_string(),
+        (1, -1) => "up_right".to_string(),
+        (-1, 1) => "down_left".to_string(),
+        (1, 1) => "down_right".to_string(),
+        (dx, dy) => format!("{}_{}", dx, dy)
+    }
+}
+
+struct Views {
+    heights: Vec<Vec<usize>>,
+    directions: Vec<(String, View)>,
+    visible: Vec<Vec<bool>>,
+    scores: Vec<Vec<usize>>,
+}
+
+impl Views {
+    fn width(&self) -> usize {
+        self.heights.first().map_or(0, |row| row.len())
+    }
+
+    fn height(&self) -> usize {
+        self.heights.len()
+    }
+
+    // The first tree with the highest scenic score, in reading order.
+    fn best(&self) -> Option<(usize, usize)> {
+        let mut best: Option<(usize, usize)> = None;
+        for y in 0..self.height() {
+            for x in 0..self.width() {
+                if best.is_none_or(|(bx, by)| self.scores[y][x] > self.scores[by][bx]) {
+                    best = Some((x, y));
+                }
+            }
+        }
+        best
+    }
+
+    fn neighbours(&self, x: usize, y: usize) -> Vec<(usize, usize)> {
+        [(-1, 0), (1, 0), (0, -1), (0, 1)].iter()
+            .map(|(dx, dy)| (x as i32 + dx, y as i32 + dy))
+            .filter(|(nx, ny)| *nx >= 0 && *ny >= 0 && (*nx as usize) < self.width() && (*ny as usize) < self.height())
+            .map(|(nx, ny)| (nx as usize, ny as usize))
+            .collect()
+    }
+
+    fn to_csv(&self) -> String {
+        let mut header = vec!["x".to_string(), "y".to_string(), "height".to_string()];
+        header.extend(self.directions.iter().map(|(name, _)| format!("visible_{}", name)));
+        header.extend(self.directions.iter().map(|(name, _)| format!("distance_{}", name)));
+        header.push("scenic_score".to_string());
+
+        let mut csv = header.join(",") + "\n";
+        for y in 0..self.height() {
+            for x in 0..self.width() {
+                let mut row = vec![x.to_string(), y.to_string(), self.heights[y][x].to_string()];
+                row.extend(self.directions.iter().map(|(_, view)| view.visible[y][x].to_string()));
+                row.extend(self.directions.iter().map(|(_, view)| view.distance[y][x].to_string()));
+                row.push(self.scores[y][x].to_string());
+                csv += &(row.join(",") + "\n");
             }
         }
+        csv
     }
 
-    score
+    // Scenic scores span several orders of magnitude, so they're mapped to brightness on a log scale.
+    fn brightness(&self) -> Vec<Vec<f64>> {
+        let max = self.scores.iter().flatten().copied().max().unwrap_or(0);
+        let scale = ((max + 1) as f64).ln().max(f64::MIN_POSITIVE);
+        self.scores.iter()
+            .map(|row| row.iter().map(|score| ((score + 1) as f64).ln() / scale).collect())
+            .collect()
+    }
+
+    fn heatmap_ansi(&self) -> String {
+        let mut out = String::new();
+        for row in self.brightness() {
+            for brightness in row {
+                // 232..=255 is the grayscale ramp of the 256 color palette.
+                let color = 232 + (brightness * 23.0).round() as usize;
+                out += &format!("\x1b[48;5;{}m  ", color);
+            }
+            out += "\x1b[0m\n";
+        }
+        out
+    }
+
+    // A binary PGM image, one pixel per tree.
+    fn heatmap_pgm(&self) -> Vec<u8> {
+        let mut out = format!("P5\n{} {}\n255\n", self.width(), self.height()).into_bytes();
+        for row in self.brightness() {
+            out.extend(row.iter().map(|brightness| (brightness * 255.0).round() as u8));
+        }
+        out
+    }
+}
+
+fn num_visible(input: &str) -> usize {
+    num_visible_towards(input, &Directions::Axes)
+}
+
+fn num_visible_towards(input: &str, directions: &Directions) -> usize {
+    Matrix::from(input).views_towards(directions)
+        .visible
+        .iter()
+        .flatten()
+        .filter(|visible| **visible)
+        .count()
+}
+
+fn max_score(input: &str) -> usize {
+    max_score_towards(input, &Directions::Axes)
+}
+
+fn max_score_towards(input: &str, directions: &Directions) -> usize {
+    Matrix::from(input).views_towards(directions)
+        .scores
+        .iter()
+        .flatten()
+        .copied()
+        .max()
+        .unwrap_or(0)
 }
 
 fn main() {
     println!("num_visible: {}", num_visible(include_str!("day8.txt")));
     println!("max_score: {}", max_score(include_str!("day8.txt")));
+
+    println!("num_visible (8 directions): {}", num_visible_towards(include_str!("day8.txt"), &Directions::AxesAndDiagonals));
+    println!("max_score (8 directions): {}", max_score_towards(include_str!("day8.txt"), &Directions::AxesAndDiagonals));
+
+    let views = Matrix::from(include_str!("day8.txt")).views();
+    let (x, y) = views.best().unwrap();
+    println!("best tree: ({}, {}), height {}", x, y, views.heights[y][x]);
+    for (nx, ny) in views.neighbours(x, y) {
+        println!("  neighbour ({}, {}): height {}, scenic score {}", nx, ny, views.heights[ny][nx], views.scores[ny][nx]);
+    }
+
+    match std::env::args().nth(1).as_deref() {
+        Some("csv") => print!("{}", views.to_csv()),
+        Some("heatmap") => print!("{}", views.heatmap_ansi()),
+        Some("pgm") => std::fs::write("day8.pgm", views.heatmap_pgm()).unwrap(),
+        Some("analyze") => analyze(std::env::args().skip(2).collect()),
+        _ => ()
+    }
+}
+
+// `analyze <file> [digits|hex|letters|numbers] [axes|diagonals|knight|<dx>,<dy>...]`
+fn analyze(args: Vec<String>) {
+    let input = std::fs::read_to_string(args.first().expect("missing input file")).unwrap();
+    let encoding = match args.get(1).map(|arg| arg.as_str()) {
+        None | Some("digits") => Encoding::Digits,
+        Some("hex") => Encoding::Hex,
+        Some("letters") => Encoding::Letters,
+        Some("numbers") => Encoding::Numbers,
+        Some(other) => panic!("unknown encoding `{}`", other),
+    };
+    let directions = match args.get(2).map(|arg| arg.as_str()) {
+        None | Some("axes") => Directions::Axes,
+        Some("diagonals") => Directions::AxesAndDiagonals,
+        Some("knight") => Directions::KnightMoves,
+        Some(_) => {
+            let offsets = args[2..].iter().map(|offset| {
+                let (dx, dy) = offset.split_once(',').expect("offsets look like `dx,dy`");
+                (dx.parse().unwrap(), dy.parse().unwrap())
+            }).collect();
+            Directions::custom(offsets).unwrap_or_else(|e| panic!("{}", e))
+        }
+    };
+
+    match Matrix::parse(&input, encoding) {
+        Ok(mtx) => {
+            let views = mtx.views_towards(&directions);
+            println!("num_visible: {}", views.visible.iter().flatten().filter(|visible| **visible).count());
+            println!("max_score: {}", views.scores.iter().flatten().max().unwrap_or(&0));
+        }
+        Err(e) => println!("{}", e),
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use rand::Rng;
 
     #[test]
     fn test() {
@@ -124,4 +466,77 @@ mod tests {
 
         assert_eq!(max_score(input), 8);
     }
+
+    #[test]
+    fn test_views_match_rays() {
+        let mut rng = rand::thread_rng();
+        for _ in 0..20 {
+            let (width, height) = (rng.gen_range(1..12), rng.gen_range(1..12));
+            let mtx = Matrix {
+                rows: (0..height).map(|_| (0..width).map(|_| rng.gen_range(0..10)).collect()).collect()
+            };
+
+            let directions = [
+                Directions::Axes,
+                Directions::AxesAndDiagonals,
+                Directions::KnightMoves,
+                Directions::custom(vec![(3, -1), (0, 2)]).unwrap(),
+            ];
+            for directions in directions {
+                let views = mtx.views_towards(&directions);
+                for y in 0..height {
+                    for x in 0..width {
+                        let pos = (x as i32, y as i32);
+                        let visible = directions.offsets().into_iter().any(|offset| mtx.vis_from(pos, offset));
+                        assert_eq!(views.visible[y][x], visible);
+                        assert_eq!(views.scores[y][x], mtx.scenic_score_towards(x, y, &directions));
+                    }
+                }
+            }
+        }
+        assert_eq!(Directions::custom(vec![(1, 1), (0, 0)]).err(), Some(ZeroOffsetError));
+    }
+
+    #[test]
+    fn test_analysis() {
+        let input = r"30373
+25512
+65332
+33549
+35390";
+        let views = Matrix::from(input).views();
+        assert_eq!(views.best(), Some((2, 3)));
+        assert_eq!(views.neighbours(2, 3), vec![(1, 3), (3, 3), (2, 2), (2, 4)]);
+        assert_eq!(views.neighbours(0, 0), vec![(1, 0), (0, 1)]);
+
+        let csv = views.to_csv();
+        let mut lines = csv.lines();
+        assert_eq!(lines.next(), Some("x,y,height,visible_left,visible_right,visible_up,visible_down,distance_left,distance_right,distance_up,distance_down,scenic_score"));
+        assert_eq!(lines.nth(7), Some("2,1,5,false,true,true,false,1,2,1,2,4"));
+        assert_eq!(csv.lines().count(), 26);
+
+        let pgm = views.heatmap_pgm();
+        assert!(pgm.starts_with(b"P5\n5 5\n255\n"));
+        assert_eq!(pgm.len(), "P5\n5 5\n255\n".len() + 25);
+        assert_eq!(pgm[pgm.len() - 25 + 2 + 3 * 5], 255);
+        assert_eq!(views.heatmap_ansi().lines().count(), 5);
+    }
+
+    #[test]
+    fn test_encodings() {
+        let digits = Matrix::parse("30373\n25512", Encoding::Digits).unwrap();
+        assert_eq!(digits.rows, vec![vec![3, 0, 3, 7, 3], vec![2, 5, 5, 1, 2]]);
+        let hex = Matrix::parse("0aF\nb19", Encoding::Hex).unwrap();
+        assert_eq!(hex.rows, vec![vec![0, 10, 15], vec![11, 1, 9]]);
+        let letters = Matrix::parse("abz\nzyx", Encoding::Letters).unwrap();
+        assert_eq!(letters.rows, vec![vec![0, 1, 25], vec![25, 24, 23]]);
+        let numbers = Matrix::parse("10  200 3\n4 5 6000", Encoding::Numbers).unwrap();
+        assert_eq!(numbers.rows, vec![vec![10, 200, 3], vec![4, 5, 6000]]);
+        assert_eq!(Matrix::parse("", Encoding::Digits).unwrap().width(), 0);
+
+        assert_eq!(Matrix::parse("303\n2x5", Encoding::Digits).err(), Some(ParseError::InvalidHeight { line: 2, column: 2, found: "x".to_string() }));
+        assert_eq!(Matrix::parse("aB", Encoding::Letters).err(), Some(ParseError::InvalidHeight { line: 1, column: 2, found: "B".to_string() }));
+        assert_eq!(Matrix::parse("1 2\n3 -4", Encoding::Numbers).err(), Some(ParseError::InvalidHeight { line: 2, column: 3, found: "-4".to_string() }));
+        assert_eq!(Matrix::parse("303\n25\n111", Encoding::Digits).err(), Some(ParseError::RaggedRow { line: 2, expected: 3, found: 2 }));
+    }
 }
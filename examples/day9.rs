@@ -1,36 +1,201 @@
-use std::collections::BTreeSet;
-use glam::IVec2;
+use std::collections::{BTreeMap, BTreeSet};
 
-#[derive(Default)]
-struct Rope {
-    head: IVec2,
-    knots: Vec<IVec2>,
-    visited_tail_positions: BTreeSet<(i32, i32)>
+// Each axis can be moved along in both directions, the first letter moves towards positive coordinates.
+const AXES: [(&str, &str); 3] = [("R", "L"), ("D", "U"), ("F", "B")];
+
+fn chebyshev<const N: usize>(diff: [i32; N]) -> i32 {
+    diff.iter().map(|d| d.abs()).max().unwrap_or(0)
+}
+
+fn diff<const N: usize>(to: [i32; N], from: [i32; N]) -> [i32; N] {
+    std::array::from_fn(|axis| to[axis] - from[axis])
+}
+
+// Decides where a knot ends up after the knot in front of it (its leader) moved.
+trait FollowRule<const N: usize> {
+    fn follow(&self, leader_before: [i32; N], leader: [i32; N], knot: [i32; N]) -> [i32; N];
+}
+
+// The puzzle's rule: once the leader is no longer touching, take a single step towards it, diagonally if needed.
+#[derive(Clone, Copy, Debug)]
+struct Touching;
+
+impl<const N: usize> FollowRule<N> for Touching {
+    fn follow(&self, leader_before: [i32; N], leader: [i32; N], knot: [i32; N]) -> [i32; N] {
+        Elastic { slack: 1 }.follow(leader_before, leader, knot)
+    }
+}
+
+// Like `Touching`, but the leader can get `slack` steps away before the knot starts following.
+#[derive(Clone, Copy, Debug)]
+struct Elastic {
+    slack: i32
+}
+
+impl<const N: usize> FollowRule<N> for Elastic {
+    fn follow(&self, _leader_before: [i32; N], leader: [i32; N], mut knot: [i32; N]) -> [i32; N] {
+        let diff = diff(leader, knot);
+        if chebyshev(diff) > self.slack {
+            for (k, d) in knot.iter_mut().zip(diff) {
+                *k += d.clamp(-1, 1);
+            }
+        }
+        knot
+    }
+}
+
+// Never moves diagonally: closes the gap along the axis with the largest distance first.
+#[derive(Clone, Copy, Debug)]
+struct Orthogonal;
+
+impl<const N: usize> FollowRule<N> for Orthogonal {
+    fn follow(&self, _leader_before: [i32; N], leader: [i32; N], mut knot: [i32; N]) -> [i32; N] {
+        loop {
+            let diff = diff(leader, knot);
+            if chebyshev(diff) <= 1 {
+                return knot;
+            }
+            let axis = (0..N).max_by_key(|axis| (diff[*axis].abs(), std::cmp::Reverse(*axis))).unwrap();
+            knot[axis] += diff[axis].signum();
+        }
+    }
 }
 
-impl Rope {
+// Snake-like: once the leader is no longer touching, jump to wherever the leader was before it moved.
+#[derive(Clone, Copy, Debug)]
+struct Lagging;
+
+impl<const N: usize> FollowRule<N> for Lagging {
+    fn follow(&self, leader_before: [i32; N], leader: [i32; N], knot: [i32; N]) -> [i32; N] {
+        if chebyshev(diff(leader, knot)) > 1 {
+            leader_before
+        } else {
+            knot
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Visit {
+    count: usize,
+    first_step: usize,
+}
+
+// Every cell a single knot has moved into, counting the cell it started in as a visit at step 0.
+#[derive(Clone, Debug, Default)]
+struct Trail<const N: usize> {
+    visits: BTreeMap<[i32; N], Visit>
+}
+
+impl<const N: usize> Trail<N> {
+    fn record(&mut self, pos: [i32; N], step: usize) {
+        self.visits.entry(pos)
+            .and_modify(|visit| visit.count += 1)
+            .or_insert(Visit { count: 1, first_step: step });
+    }
+
+    fn len(&self) -> usize {
+        self.visits.len()
+    }
+
+    fn visits(&self, pos: [i32; N]) -> usize {
+        self.visits.get(&pos).map_or(0, |visit| visit.count)
+    }
+
+    fn first_visit(&self, pos: [i32; N]) -> Option<usize> {
+        self.visits.get(&pos).map(|visit| visit.first_step)
+    }
+}
+
+struct Rope<const N: usize> {
+    head: [i32; N],
+    knots: Vec<[i32; N]>,
+    rules: Vec<Box<dyn FollowRule<N>>>,
+    visited_tail_positions: BTreeSet<[i32; N]>,
+    step: usize,
+    // One trail per knot, with the head at index 0. Only recorded when asked for.
+    trails: Option<Vec<Trail<N>>>,
+}
+
+impl<const N: usize> Rope<N> {
     pub fn new(num_knots: usize) -> Self {
+        Self::with_rule(num_knots, Touching)
+    }
+
+    pub fn with_rule(num_knots: usize, rule: impl FollowRule<N> + Clone + 'static) -> Self {
+        Self::with_rules((0..num_knots).map(|_| Box::new(rule.clone()) as Box<dyn FollowRule<N>>).collect())
+    }
+
+    // One rule per knot, starting with the knot right behind the head.
+    pub fn with_rules(rules: Vec<Box<dyn FollowRule<N>>>) -> Self {
         Self {
-            head: IVec2::ZERO,
-            knots: vec![IVec2::ZERO; num_knots],
-            visited_tail_positions: BTreeSet::new()
+            head: [0; N],
+            knots: vec![[0; N]; rules.len()],
+            rules,
+            visited_tail_positions: BTreeSet::new(),
+            step: 0,
+            trails: None
+        }
+    }
+
+    pub fn record_trails(mut self) -> Self {
+        let mut trails = vec![Trail::default(); self.knots.len() + 1];
+        for (trail, pos) in trails.iter_mut().zip(self.positions()) {
+            trail.record(pos, self.step);
+        }
+        self.trails = Some(trails);
+        self
+    }
+
+    // The head followed by every knot.
+    fn positions(&self) -> impl Iterator<Item = [i32; N]> + '_ {
+        std::iter::once(self.head).chain(self.knots.iter().copied())
+    }
+
+    // Knot 0 is the head, like in the puzzle's diagrams.
+    pub fn visited_by(&self, knot: usize) -> Option<&Trail<N>> {
+        self.trails.as_ref()?.get(knot)
+    }
+
+    // The cell visited most often by all knots combined, preferring the smallest cell on ties.
+    pub fn most_visited(&self) -> Option<([i32; N], usize)> {
+        let mut totals: BTreeMap<[i32; N], usize> = BTreeMap::new();
+        for trail in self.trails.as_ref()? {
+            for (pos, visit) in &trail.visits {
+                *totals.entry(*pos).or_default() += visit.count;
+            }
         }
+        totals.into_iter().fold(None, |best, (pos, count)| match best {
+            Some((_, best_count)) if best_count >= count => best,
+            _ => Some((pos, count))
+        })
     }
 
-    pub fn move_head(&mut self, offset: IVec2) {
-        self.head += offset;
+    pub fn move_head(&mut self, offset: [i32; N]) {
+        self.step += 1;
+        let mut trails = self.trails.as_mut();
 
-        let mut prev_knot = self.head;
-        for knot in &mut self.knots {
-            let diff = prev_knot - *knot;
-            if diff.abs().max_element() > 1 {
-                *knot += diff.clamp(IVec2::NEG_ONE, IVec2::ONE);
+        let mut leader_before = self.head;
+        for (head, offset) in self.head.iter_mut().zip(offset) {
+            *head += offset;
+        }
+        if let Some(trails) = trails.as_mut().filter(|_| self.head != leader_before) {
+            trails[0].record(self.head, self.step);
+        }
+
+        let mut leader = self.head;
+        for (idx, (knot, rule)) in self.knots.iter_mut().zip(&self.rules).enumerate() {
+            let knot_before = *knot;
+            *knot = rule.follow(leader_before, leader, *knot);
+            if let Some(trails) = trails.as_mut().filter(|_| *knot != knot_before) {
+                trails[idx + 1].record(*knot, self.step);
             }
-            prev_knot = *knot;
+            leader_before = knot_before;
+            leader = *knot;
         }
 
         let tail = self.knots.last().unwrap();
-        self.visited_tail_positions.insert((tail.x, tail.y));
+        self.visited_tail_positions.insert(*tail);
     }
 
     pub fn num_tail_positions(&self) -> usize {
@@ -38,40 +203,247 @@ impl Rope {
     }
 }
 
-fn read_moves(input: &str) -> Vec<IVec2> {
+fn knot_label(knot: usize) -> char {
+    match knot {
+        0 => 'H',
+        1..=9 => char::from_digit(knot as u32, 10).unwrap(),
+        _ => '#'
+    }
+}
+
+impl Rope<2> {
+    // Overlays every knot's trail, with knots further down the rope drawn on top. `s` marks the start.
+    pub fn render_trails(&self) -> Option<String> {
+        let trails = self.trails.as_ref()?;
+        let cells = trails.iter().flat_map(|trail| trail.visits.keys()).collect::<Vec<_>>();
+        let min_x = cells.iter().map(|pos| pos[0]).min()?;
+        let max_x = cells.iter().map(|pos| pos[0]).max()?;
+        let min_y = cells.iter().map(|pos| pos[1]).min()?;
+        let max_y = cells.iter().map(|pos| pos[1]).max()?;
+
+        let mut out = String::new();
+        for y in min_y..=max_y {
+            for x in min_x..=max_x {
+                let c = if [x, y] == [0, 0] {
+                    's'
+                } else {
+                    (0..trails.len()).rev()
+                        .find(|knot| trails[*knot].visits([x, y]) > 0)
+                        .map_or('.', knot_label)
+                };
+                out.push(c);
+            }
+            out.push('\n');
+        }
+        Some(out)
+    }
+}
+
+fn read_moves<const N: usize>(input: &str) -> Vec<[i32; N]> {
     input.lines()
         .map(|line| {
             let (dir, amount) = line.split_at(1);
-            let amount = usize::from_str_radix(amount.trim(), 10).unwrap() as i32;
-
-            match dir {
-                "R" => IVec2::new(amount, 0),
-                "L" => IVec2::new(-amount, 0),
-                "U" => IVec2::new(0, -amount),
-                "D" => IVec2::new(0, amount),
-                _ => panic!("lol wat")
-            }
+            let amount = amount.trim().parse::<i32>().unwrap();
+
+            let (axis, sign) = AXES.iter()
+                .enumerate()
+                .find_map(|(axis, (pos, neg))| {
+                    if dir == *pos {
+                        Some((axis, 1))
+                    } else if dir == *neg {
+                        Some((axis, -1))
+                    } else {
+                        None
+                    }
+                })
+                .expect("lol wat");
+            assert!(axis < N, "can't move along axis {} in {} dimensions", dir, N);
+
+            let mut m = [0; N];
+            m[axis] = sign * amount;
+            m
         })
         .collect()
 }
 
-fn apply_moves(num_knots: usize, input: &str) -> Rope {
-    let mut rope = Rope::new(num_knots);
-    let moves = read_moves(input);
-    for m in moves {
-        let amount = m.abs().max_element();
-        let m = m.clamp(IVec2::NEG_ONE, IVec2::ONE);
+fn apply_moves<const N: usize>(num_knots: usize, input: &str) -> Rope<N> {
+    apply_moves_to(Rope::new(num_knots), input)
+}
 
-        for _ in 0..amount {
-            rope.move_head(m);
-        }
+// Splits every move into steps of a single cell.
+fn unit_moves<const N: usize>(input: &str) -> Vec<[i32; N]> {
+    read_moves::<N>(input)
+        .into_iter()
+        .flat_map(|m| {
+            let amount = m.iter().map(|d| d.abs()).max().unwrap_or(0);
+            std::iter::repeat_n(m.map(|d| d.clamp(-1, 1)), amount as usize)
+        })
+        .collect()
+}
+
+fn apply_moves_to<const N: usize>(mut rope: Rope<N>, input: &str) -> Rope<N> {
+    for m in unit_moves::<N>(input) {
+        rope.move_head(m);
     }
     rope
 }
 
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct RopeState<const N: usize> {
+    step: usize,
+    head: [i32; N],
+    knots: Vec<[i32; N]>,
+}
+
+impl<const N: usize> RopeState<N> {
+    fn of(rope: &Rope<N>) -> Self {
+        Self {
+            step: rope.step,
+            head: rope.head,
+            knots: rope.knots.clone()
+        }
+    }
+}
+
+impl RopeState<2> {
+    // Draws the rope like the puzzle's diagrams, within `bounds` (inclusive) or just around the rope
+    // and the start if none are given. Knots closer to the head cover the ones behind them.
+    fn render(&self, bounds: Option<([i32; 2], [i32; 2])>) -> String {
+        let positions = std::iter::once(self.head).chain(self.knots.iter().copied()).collect::<Vec<_>>();
+        let (min, max) = bounds.unwrap_or_else(|| {
+            positions.iter().fold(([0, 0], [0, 0]), |(min, max), pos| {
+                ([min[0].min(pos[0]), min[1].min(pos[1])], [max[0].max(pos[0]), max[1].max(pos[1])])
+            })
+        });
+
+        let mut out = String::new();
+        for y in min[1]..=max[1] {
+            for x in min[0]..=max[0] {
+                let c = match positions.iter().position(|pos| *pos == [x, y]) {
+                    Some(1) if self.knots.len() == 1 => 'T',
+                    Some(knot) => knot_label(knot),
+                    None if [x, y] == [0, 0] => 's',
+                    None => '.'
+                };
+                out.push(c);
+            }
+            out.push('\n');
+        }
+        out
+    }
+}
+
+// Replays moves one unit step at a time. Every state that has been visited is kept around, so jumping
+// back doesn't require replaying from the start.
+struct Replay<const N: usize> {
+    rope: Rope<N>,
+    moves: Vec<[i32; N]>,
+    history: Vec<RopeState<N>>,
+    // The rope may already have moved before the replay started, `moves` and `history` count from here.
+    start: usize,
+}
+
+impl<const N: usize> Replay<N> {
+    fn new(rope: Rope<N>, input: &str) -> Self {
+        let history = vec![RopeState::of(&rope)];
+        Self {
+            start: rope.step,
+            rope,
+            moves: unit_moves::<N>(input),
+            history
+        }
+    }
+
+    fn state(&self) -> &RopeState<N> {
+        self.history.last().unwrap()
+    }
+
+    fn num_steps(&self) -> usize {
+        self.moves.len()
+    }
+
+    fn jump_to(&mut self, step: usize) -> Option<RopeState<N>> {
+        while self.history.len() <= step {
+            self.next()?;
+        }
+        Some(self.history[step].clone())
+    }
+
+    // Keeps stepping until `knot` (0 being the head) moves, and returns the state right after it did.
+    fn break_on_move(&mut self, knot: usize) -> Option<RopeState<N>> {
+        loop {
+            let before = self.rope.positions().nth(knot)?;
+            let state = self.next()?;
+            if self.rope.positions().nth(knot) != Some(before) {
+                return Some(state);
+            }
+        }
+    }
+}
+
+impl<const N: usize> Iterator for Replay<N> {
+    type Item = RopeState<N>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let m = *self.moves.get(self.rope.step - self.start)?;
+        self.rope.move_head(m);
+        let state = RopeState::of(&self.rope);
+        self.history.push(state.clone());
+        Some(state)
+    }
+}
+
 fn main() {
-    println!("num tail positions with 1 knot: {}", apply_moves(1, include_str!("day9.txt")).num_tail_positions());
-    println!("num tail positions with 9 knots: {}", apply_moves(9, include_str!("day9.txt")).num_tail_positions());
+    println!("num tail positions with 1 knot: {}", apply_moves::<2>(1, include_str!("day9.txt")).num_tail_positions());
+    println!("num tail positions with 9 knots: {}", apply_moves::<2>(9, include_str!("day9.txt")).num_tail_positions());
+
+    let ropes: [(&str, Rope<2>); 4] = [
+        ("touching", Rope::with_rule(9, Touching)),
+        ("orthogonal", Rope::with_rule(9, Orthogonal)),
+        ("elastic (slack 2)", Rope::with_rule(9, Elastic { slack: 2 })),
+        ("lagging", Rope::with_rule(9, Lagging)),
+    ];
+    let rope = apply_moves_to(Rope::<2>::new(9).record_trails(), include_str!("day9.txt"));
+    for knot in 0..=9 {
+        println!("knot {} visited {} cells", knot_label(knot), rope.visited_by(knot).unwrap().len());
+    }
+    if let Some((pos, count)) = rope.most_visited() {
+        println!("most visited cell: {:?} ({} visits)", pos, count);
+        let first = (0..=9)
+            .filter_map(|knot| rope.visited_by(knot)?.first_visit(pos).map(|step| (step, knot)))
+            .min();
+        if let Some((step, knot)) = first {
+            println!("first reached by knot {} at step {}", knot_label(knot), step);
+        }
+    }
+    if std::env::args().nth(1).as_deref() == Some("render") {
+        print!("{}", rope.render_trails().unwrap());
+    }
+
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    match args.iter().map(String::as_str).collect::<Vec<_>>().as_slice() {
+        ["replay", "break", knot] => {
+            let mut replay = Replay::new(Rope::<2>::new(9), include_str!("day9.txt"));
+            match replay.break_on_move(knot.parse().unwrap()) {
+                Some(_) => {
+                    println!("knot {} first moves at step {} of {}:", knot, replay.state().step, replay.num_steps());
+                    print!("{}", replay.state().render(None));
+                }
+                None => println!("knot {} never moves", knot),
+            }
+        }
+        ["replay", step] => {
+            let mut replay = Replay::new(Rope::<2>::new(9), include_str!("day9.txt"));
+            let state = replay.jump_to(step.parse().unwrap()).expect("not that many steps");
+            println!("after step {} of {}:", state.step, replay.num_steps());
+            print!("{}", state.render(None));
+        }
+        _ => ()
+    }
+
+    for (name, rope) in ropes {
+        println!("num tail positions with 9 {} knots: {}", name, apply_moves_to(rope, include_str!("day9.txt")).num_tail_positions());
+    }
 }
 
 #[cfg(test)]
@@ -88,10 +460,121 @@ R 4
 D 1
 L 5
 R 2"#;
-        let moves = read_moves(input);
-        assert_eq!(moves[1], IVec2::new(0, -4));
+        let moves = read_moves::<2>(input);
+        assert_eq!(moves[1], [0, -4]);
 
-        let rope = apply_moves(1, input);
+        let rope = apply_moves::<2>(1, input);
         assert_eq!(rope.num_tail_positions(), 13);
     }
+
+    #[test]
+    fn test_dimensions() {
+        let input = r#"F 2
+R 2"#;
+        let moves = read_moves::<3>(input);
+        assert_eq!(moves, vec![[0, 0, 2], [2, 0, 0]]);
+
+        let rope = apply_moves::<3>(1, input);
+        assert_eq!(rope.visited_tail_positions, BTreeSet::from([[0, 0, 0], [0, 0, 1], [1, 0, 2]]));
+
+        let rope = apply_moves::<4>(2, "B 5");
+        assert_eq!(rope.knots, vec![[0, 0, -4, 0], [0, 0, -3, 0]]);
+        assert_eq!(rope.num_tail_positions(), 4);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_missing_axis() {
+        read_moves::<2>("F 1");
+    }
+
+    #[test]
+    fn test_follow_rules() {
+        let input = r#"R 4
+U 4
+L 3
+D 1
+R 4
+D 1
+L 5
+R 2"#;
+        assert_eq!(apply_moves_to(Rope::<2>::with_rule(1, Elastic { slack: 1 }), input).num_tail_positions(), 13);
+        // With a single knot, jumping to where the head was is the same as the puzzle's rule.
+        assert_eq!(apply_moves_to(Rope::<2>::with_rule(1, Lagging), input).num_tail_positions(), 13);
+
+        let rope = apply_moves_to(Rope::<2>::with_rule(1, Orthogonal), "R 2\nU 2");
+        assert_eq!(rope.knots, vec![[1, -1]]);
+        let rope = apply_moves_to(Rope::<2>::with_rule(1, Touching), "R 2\nU 2");
+        assert_eq!(rope.knots, vec![[2, -1]]);
+
+        let rope = apply_moves_to(Rope::<2>::with_rule(1, Elastic { slack: 2 }), "R 5");
+        assert_eq!(rope.knots, vec![[3, 0]]);
+        assert_eq!(rope.num_tail_positions(), 4);
+
+        let rope = apply_moves_to(Rope::<2>::with_rules(vec![Box::new(Elastic { slack: 2 }), Box::new(Touching)]), "R 5");
+        assert_eq!(rope.knots, vec![[3, 0], [2, 0]]);
+    }
+
+    #[test]
+    fn test_trails() {
+        let rope = apply_moves_to(Rope::<2>::new(2).record_trails(), "R 3\nL 3");
+        assert!(Rope::<2>::new(2).visited_by(0).is_none());
+
+        let head = rope.visited_by(0).unwrap();
+        assert_eq!(head.len(), 4);
+        assert_eq!(head.visits([0, 0]), 2);
+        assert_eq!(head.visits([1, 0]), 2);
+        assert_eq!(head.visits([3, 0]), 1);
+        assert_eq!(head.first_visit([2, 0]), Some(2));
+
+        let tail = rope.visited_by(2).unwrap();
+        assert_eq!(tail.len(), 2);
+        assert_eq!(tail.first_visit([1, 0]), Some(3));
+        assert_eq!(tail.visits([5, 0]), 0);
+        assert!(rope.visited_by(3).is_none());
+
+        assert_eq!(rope.most_visited(), Some(([1, 0], 5)));
+        assert_eq!(rope.render_trails().unwrap(), "s21H\n");
+    }
+
+    #[test]
+    fn test_replay() {
+        let input = r#"R 4
+U 4
+L 3
+D 1
+R 4
+D 1
+L 5
+R 2"#;
+        let bounds = Some(([0, -4], [5, 0]));
+        let mut replay = Replay::new(Rope::<2>::new(1), input);
+        assert_eq!(replay.num_steps(), 24);
+        assert_eq!(replay.state().render(bounds), "......\n......\n......\n......\nH.....\n");
+
+        let state = replay.break_on_move(1).unwrap();
+        assert_eq!(state.step, 2);
+        assert_eq!(state.render(bounds), "......\n......\n......\n......\nsTH...\n");
+
+        let state = replay.jump_to(8).unwrap();
+        assert_eq!(state.render(bounds), "....H.\n....T.\n......\n......\ns.....\n");
+        assert_eq!(replay.jump_to(1).unwrap().render(bounds), "......\n......\n......\n......\nTH....\n");
+        assert_eq!(replay.state().step, 8);
+
+        let states = replay.by_ref().collect::<Vec<_>>();
+        assert_eq!(states.len(), 16);
+        assert!(replay.jump_to(25).is_none());
+
+        // A rope that has already moved replays all of the new moves, starting from where it is.
+        let mut replay = Replay::new(apply_moves_to(Rope::<2>::new(1), "U 2"), input);
+        assert_eq!(replay.jump_to(0).unwrap().step, 2);
+        assert_eq!(replay.jump_to(1).unwrap().render(Some(([0, -3], [2, 0]))), "...\n.H.\nT..\ns..\n");
+        let states = replay.by_ref().collect::<Vec<_>>();
+        assert_eq!((states.len(), states.last().unwrap().step), (23, 26));
+
+        let mut replay = Replay::new(Rope::<2>::new(9), "R 5\nU 8\nL 8");
+        let state = replay.break_on_move(9).unwrap();
+        assert_eq!(state.step, 17);
+        assert_eq!(state.render(None), ".H12\n...3\n...4\n...5\n...6\n...7\n..8.\n.9..\ns...\n");
+    }
 }